@@ -0,0 +1,117 @@
+//! Client authentication through a generated `pg_hba.conf`.
+//!
+//! The file is written to the temporary directory and passed to the server as `hba_file`, leaving
+//! the `pg_hba.conf` in the data directory untouched.
+
+/// Name of the generated file inside the temporary directory.
+pub(crate) const HBA_FILE: &str = "pg_hba.conf";
+
+/// Method clients authenticate with, see [`PostgresBuilder::auth`](crate::PostgresBuilder::auth).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AuthMethod {
+    /// Password authentication through SCRAM-SHA-256.
+    #[default]
+    ScramSha256,
+    /// Password authentication through MD5 hashes, which PostgreSQL has deprecated.
+    ///
+    /// Passwords set afterwards are stored as MD5 hashes.
+    Md5,
+    /// Any user can connect without a password.
+    Trust,
+    /// Unix socket connections are authenticated through the operating system user name, TCP
+    /// connections through SCRAM-SHA-256.
+    Peer,
+    /// Users are authenticated through TLS client certificates, implies
+    /// [`PostgresBuilder::tls`](crate::PostgresBuilder::tls).
+    ///
+    /// The client certificates are generated on demand and added to the URLs of
+    /// [`Postgres::as_user`](crate::Postgres::as_user).
+    #[cfg(feature = "tls")]
+    Cert,
+}
+
+impl AuthMethod {
+    /// Returns the method's name in `pg_hba.conf`.
+    fn hba_name(self) -> &'static str {
+        match self {
+            AuthMethod::ScramSha256 => "scram-sha-256",
+            AuthMethod::Md5 => "md5",
+            AuthMethod::Trust => "trust",
+            AuthMethod::Peer => "peer",
+            #[cfg(feature = "tls")]
+            AuthMethod::Cert => "cert",
+        }
+    }
+
+    /// Returns the method the superuser authenticates with.
+    ///
+    /// `pgdb` itself connects as the superuser with a password, so methods requiring anything
+    /// else fall back to SCRAM-SHA-256.
+    fn superuser_method(self) -> AuthMethod {
+        match self {
+            AuthMethod::ScramSha256 | AuthMethod::Md5 | AuthMethod::Trust => self,
+            _ => AuthMethod::ScramSha256,
+        }
+    }
+}
+
+/// Returns the contents of the `pg_hba.conf` for `method`.
+///
+/// `custom_lines` are placed after the superuser's rules and before the rules for everyone else, so
+/// they take precedence over the latter.
+pub(crate) fn hba_conf(method: AuthMethod, superuser: &str, custom_lines: &[String]) -> String {
+    let superuser = format!("\"{}\"", superuser.replace('"', "\"\""));
+    let superuser_method = method.superuser_method().hba_name();
+
+    let mut lines = vec![
+        "# Generated by pgdb.".to_string(),
+        format!("local all {superuser} {superuser_method}"),
+        format!("host all {superuser} all {superuser_method}"),
+        // Replicas stream changes as the superuser.
+        format!("local replication {superuser} {superuser_method}"),
+        format!("host replication {superuser} all {superuser_method}"),
+    ];
+    lines.extend(custom_lines.iter().cloned());
+    match method {
+        AuthMethod::Peer => {
+            lines.push("local all all peer".to_string());
+            lines.push("host all all all scram-sha-256".to_string());
+        }
+        #[cfg(feature = "tls")]
+        AuthMethod::Cert => lines.push("hostssl all all all cert".to_string()),
+        _ => {
+            lines.push(format!("local all all {}", method.hba_name()));
+            lines.push(format!("host all all all {}", method.hba_name()));
+        }
+    }
+
+    let mut conf = lines.join("\n");
+    conf.push('\n');
+    conf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hba_conf, AuthMethod};
+
+    #[test]
+    fn custom_lines_precede_general_rules() {
+        let conf = hba_conf(
+            AuthMethod::Peer,
+            "postgres",
+            &["local all blocked reject".to_string()],
+        );
+        assert_eq!(
+            conf,
+            "# Generated by pgdb.\n\
+             local all \"postgres\" scram-sha-256\n\
+             host all \"postgres\" all scram-sha-256\n\
+             local replication \"postgres\" scram-sha-256\n\
+             host replication \"postgres\" all scram-sha-256\n\
+             local all blocked reject\n\
+             local all all peer\n\
+             host all all all scram-sha-256\n"
+        );
+    }
+}
@@ -0,0 +1,142 @@
+//! Locations of PostgreSQL binaries.
+
+use std::path::PathBuf;
+
+use crate::Error;
+
+/// Paths to the PostgreSQL binaries used to run servers and execute SQL.
+///
+/// Binaries that are not set are taken from the [`bin_dir`](Self::bin_dir), if it contains them,
+/// or looked up in `PATH`. The same configuration can be passed to
+/// [`PostgresBuilder::binaries`](crate::PostgresBuilder::binaries) and to free functions like
+/// [`run_psql_command`](crate::run_psql_command), so that all of them use the same installation.
+#[derive(Clone, Debug, Default)]
+pub struct PgBinaries {
+    /// Directory containing all binaries.
+    bin_dir: Option<PathBuf>,
+    /// Path to `postgres` binary.
+    postgres: Option<PathBuf>,
+    /// Path to `initdb` binary.
+    initdb: Option<PathBuf>,
+    /// Path to `pg_isready` binary.
+    pg_isready: Option<PathBuf>,
+    /// Path to `psql` binary.
+    psql: Option<PathBuf>,
+    /// Path to `pg_basebackup` binary.
+    pg_basebackup: Option<PathBuf>,
+    /// Path to `pg_ctl` binary.
+    pg_ctl: Option<PathBuf>,
+}
+
+impl PgBinaries {
+    /// Creates a configuration looking up all binaries in `PATH`.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the directory containing the binaries, e.g. `/usr/lib/postgresql/16/bin`.
+    #[inline]
+    pub fn bin_dir<T: Into<PathBuf>>(&mut self, bin_dir: T) -> &mut Self {
+        self.bin_dir = Some(bin_dir.into());
+        self
+    }
+
+    /// Returns whether the `postgres` binary or the directory containing it was set.
+    pub(crate) fn has_postgres(&self) -> bool {
+        self.postgres.is_some() || self.bin_dir.is_some()
+    }
+
+    /// Sets the location of the `postgres` binary.
+    #[inline]
+    pub fn postgres<T: Into<PathBuf>>(&mut self, postgres: T) -> &mut Self {
+        self.postgres = Some(postgres.into());
+        self
+    }
+
+    /// Sets the location of the `initdb` binary.
+    #[inline]
+    pub fn initdb<T: Into<PathBuf>>(&mut self, initdb: T) -> &mut Self {
+        self.initdb = Some(initdb.into());
+        self
+    }
+
+    /// Sets the location of the `pg_isready` binary.
+    #[inline]
+    pub fn pg_isready<T: Into<PathBuf>>(&mut self, pg_isready: T) -> &mut Self {
+        self.pg_isready = Some(pg_isready.into());
+        self
+    }
+
+    /// Sets the location of the `psql` binary.
+    #[inline]
+    pub fn psql<T: Into<PathBuf>>(&mut self, psql: T) -> &mut Self {
+        self.psql = Some(psql.into());
+        self
+    }
+
+    /// Sets the location of the `pg_basebackup` binary.
+    #[inline]
+    pub fn pg_basebackup<T: Into<PathBuf>>(&mut self, pg_basebackup: T) -> &mut Self {
+        self.pg_basebackup = Some(pg_basebackup.into());
+        self
+    }
+
+    /// Sets the location of the `pg_ctl` binary.
+    #[inline]
+    pub fn pg_ctl<T: Into<PathBuf>>(&mut self, pg_ctl: T) -> &mut Self {
+        self.pg_ctl = Some(pg_ctl.into());
+        self
+    }
+
+    /// Returns the `postgres` binary.
+    pub(crate) fn find_postgres(&self) -> Result<PathBuf, Error> {
+        self.find(&self.postgres, "postgres")
+            .map_err(Error::FindPostgres)
+    }
+
+    /// Returns the `initdb` binary.
+    pub(crate) fn find_initdb(&self) -> Result<PathBuf, Error> {
+        self.find(&self.initdb, "initdb").map_err(Error::FindInitdb)
+    }
+
+    /// Returns the `pg_isready` binary.
+    pub(crate) fn find_pg_isready(&self) -> Result<PathBuf, Error> {
+        self.find(&self.pg_isready, "pg_isready")
+            .map_err(Error::FindPgIsready)
+    }
+
+    /// Returns the `psql` binary.
+    pub(crate) fn find_psql(&self) -> Result<PathBuf, Error> {
+        self.find(&self.psql, "psql").map_err(Error::FindPsql)
+    }
+
+    /// Returns the `pg_basebackup` binary.
+    pub(crate) fn find_pg_basebackup(&self) -> Result<PathBuf, Error> {
+        self.find(&self.pg_basebackup, "pg_basebackup")
+            .map_err(Error::FindPgBasebackup)
+    }
+
+    /// Returns the `psql` binary, leaving the lookup to the OS if it cannot be found.
+    pub(crate) fn psql_or_default(&self) -> PathBuf {
+        self.find_psql().unwrap_or_else(|_| "psql".into())
+    }
+
+    /// Returns the `pg_ctl` binary, leaving the lookup to the OS if it cannot be found.
+    pub(crate) fn pg_ctl_or_default(&self) -> PathBuf {
+        self.find(&self.pg_ctl, "pg_ctl")
+            .unwrap_or_else(|_| "pg_ctl".into())
+    }
+
+    /// Returns the configured binary, the one in the binary directory or looks up `name` in `PATH`.
+    fn find(&self, configured: &Option<PathBuf>, name: &str) -> Result<PathBuf, which::Error> {
+        if let Some(path) = configured {
+            return Ok(path.clone());
+        }
+
+        match &self.bin_dir {
+            Some(bin_dir) if bin_dir.join(name).is_file() => Ok(bin_dir.join(name)),
+            _ => which::which(name),
+        }
+    }
+}
@@ -1,10 +1,13 @@
 //! Environment configuration for PostgreSQL instances.
 
-use std::time::Duration;
+use std::{env, path::PathBuf, time::Duration};
 
 use serde::Deserialize;
 
-use crate::PostgresBuilder;
+use crate::{
+    discovery::{self, VersionReq},
+    Error, PgBinaries, PostgresBuilder,
+};
 
 /// Environment-derived overrides for a PostgreSQL instance.
 #[derive(Debug, Default, Deserialize)]
@@ -27,6 +30,46 @@ pub struct PostgresEnvironment {
     force_shutdown_timeout: Option<u64>,
     /// Whether external test fixtures should be cleaned up on drop.
     tests_cleanup: Option<bool>,
+    /// Whether fixture servers should copy clusters from the initdb cache.
+    #[serde(default)]
+    initdb_cache: bool,
+    /// Whether to keep servers and fixture databases dropped during a panic.
+    #[serde(default)]
+    keep_on_failure: bool,
+    /// Whether fixtures should share a server with other processes.
+    #[serde(default)]
+    shared: bool,
+    /// Directory coordinating the shared server, implies `shared`.
+    shared_dir: Option<PathBuf>,
+    /// Directory containing the PostgreSQL binaries.
+    pg_bin_dir: Option<PathBuf>,
+    /// Required PostgreSQL version.
+    pg_version: Option<String>,
+    /// Comma-separated PostgreSQL versions for matrix fixtures.
+    pg_versions: Option<String>,
+    /// Comma-separated extensions to create in every database.
+    extensions: Option<String>,
+    /// Locale of new clusters.
+    locale: Option<String>,
+    /// Collation order of new clusters.
+    lc_collate: Option<String>,
+    /// Character classification of new clusters.
+    lc_ctype: Option<String>,
+    /// Default encoding of new clusters.
+    encoding: Option<String>,
+    /// ICU locale of new clusters.
+    icu_locale: Option<String>,
+    /// Whether to enable data checksums.
+    #[serde(default)]
+    data_checksums: bool,
+    /// Path to `postgres` binary.
+    postgres_binary: Option<PathBuf>,
+    /// Path to `initdb` binary.
+    initdb_binary: Option<PathBuf>,
+    /// Path to `pg_isready` binary.
+    pg_isready_binary: Option<PathBuf>,
+    /// Path to `psql` binary.
+    psql_binary: Option<PathBuf>,
 }
 
 impl PostgresEnvironment {
@@ -43,6 +86,9 @@ impl PostgresEnvironment {
         if self.tcp {
             builder.tcp();
         }
+        if self.keep_on_failure {
+            builder.keep_on_panic();
+        }
         if let Some(port) = self.port {
             builder.tcp().port(port);
         }
@@ -58,6 +104,63 @@ impl PostgresEnvironment {
         if let Some(force_shutdown_timeout) = self.force_shutdown_timeout {
             builder.force_shutdown_timeout(Duration::from_secs(force_shutdown_timeout));
         }
+        for extension in self.extensions() {
+            builder.extension(extension);
+        }
+        if let Some(locale) = &self.locale {
+            builder.locale(locale);
+        }
+        if let Some(lc_collate) = &self.lc_collate {
+            builder.lc_collate(lc_collate);
+        }
+        if let Some(lc_ctype) = &self.lc_ctype {
+            builder.lc_ctype(lc_ctype);
+        }
+        if let Some(encoding) = &self.encoding {
+            builder.encoding(encoding);
+        }
+        if let Some(icu_locale) = &self.icu_locale {
+            builder.icu_locale(icu_locale);
+        }
+        if self.data_checksums {
+            builder.data_checksums();
+        }
+        builder.binaries(self.binaries());
+        if let Some(pg_version) = &self.pg_version {
+            builder.version(pg_version);
+        }
+    }
+
+    /// Returns the configured binaries, looking up the remaining ones in `PATH`.
+    ///
+    /// Without a binary directory or `postgres` binary, the installation selected by
+    /// `PGDB_PG_VERSION` is used, so that servers and clients agree on the version.
+    pub fn binaries(&self) -> PgBinaries {
+        let mut binaries = PgBinaries::new();
+        if let Some(pg_bin_dir) = &self.pg_bin_dir {
+            binaries.bin_dir(pg_bin_dir);
+        } else if let (Some(pg_version), None) = (&self.pg_version, &self.postgres_binary) {
+            // On failure, starting a server reports the invalid or unsatisfied requirement.
+            if let Ok(installation) = pg_version
+                .parse()
+                .and_then(|requirement| discovery::find(&requirement))
+            {
+                binaries.bin_dir(installation.bin_dir());
+            }
+        }
+        if let Some(postgres_binary) = &self.postgres_binary {
+            binaries.postgres(postgres_binary);
+        }
+        if let Some(initdb_binary) = &self.initdb_binary {
+            binaries.initdb(initdb_binary);
+        }
+        if let Some(pg_isready_binary) = &self.pg_isready_binary {
+            binaries.pg_isready(pg_isready_binary);
+        }
+        if let Some(psql_binary) = &self.psql_binary {
+            binaries.psql(psql_binary);
+        }
+        binaries
     }
 
     /// Returns whether external test fixtures should be cleaned up on drop.
@@ -65,6 +168,57 @@ impl PostgresEnvironment {
         self.tests_cleanup.unwrap_or(true)
     }
 
+    /// Returns whether servers and fixture databases dropped during a panic should be kept.
+    pub fn keep_on_failure(&self) -> bool {
+        self.keep_on_failure
+    }
+
+    /// Returns whether fixture servers should copy clusters from the initdb cache.
+    pub fn initdb_cache(&self) -> bool {
+        self.initdb_cache
+    }
+
+    /// Returns the directory coordinating a server shared across processes, if enabled.
+    ///
+    /// Defaults to `pgdb-shared` inside `$XDG_RUNTIME_DIR`, falling back to `pgdb-shared-<uid>`
+    /// inside the system's temporary directory, so that every user gets their own server.
+    pub fn shared_dir(&self) -> Option<PathBuf> {
+        if let Some(shared_dir) = &self.shared_dir {
+            Some(shared_dir.clone())
+        } else if self.shared {
+            Some(match env::var_os("XDG_RUNTIME_DIR") {
+                Some(runtime_dir) if !runtime_dir.is_empty() => {
+                    PathBuf::from(runtime_dir).join("pgdb-shared")
+                }
+                // SAFETY: `geteuid` cannot fail and has no preconditions.
+                _ => env::temp_dir().join(format!("pgdb-shared-{}", unsafe { libc::geteuid() })),
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the version requirements for matrix fixtures, empty if not configured.
+    pub fn pg_versions(&self) -> Result<Vec<VersionReq>, Error> {
+        self.pg_versions
+            .iter()
+            .flat_map(|pg_versions| pg_versions.split(','))
+            .filter(|requirement| !requirement.trim().is_empty())
+            .map(str::parse)
+            .collect()
+    }
+
+    /// Returns the extensions to create in every database.
+    pub fn extensions(&self) -> Vec<String> {
+        self.extensions
+            .iter()
+            .flat_map(|extensions| extensions.split(','))
+            .map(str::trim)
+            .filter(|extension| !extension.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
     /// Returns whether TCP was requested.
     pub fn tcp(&self) -> bool {
         self.tcp
@@ -92,6 +246,22 @@ mod tests {
                 ("PGDB_SHUTDOWN_TIMEOUT".to_string(), "7".to_string()),
                 ("PGDB_FORCE_SHUTDOWN_TIMEOUT".to_string(), "2".to_string()),
                 ("PGDB_TESTS_CLEANUP".to_string(), "false".to_string()),
+                ("PGDB_INITDB_CACHE".to_string(), "true".to_string()),
+                ("PGDB_SHARED_DIR".to_string(), "/tmp/shared".to_string()),
+                ("PGDB_KEEP_ON_FAILURE".to_string(), "true".to_string()),
+                (
+                    "PGDB_PSQL_BINARY".to_string(),
+                    "/opt/pg/bin/psql".to_string(),
+                ),
+                ("PGDB_PG_VERSION".to_string(), ">=15".to_string()),
+                ("PGDB_PG_VERSIONS".to_string(), "13, 17".to_string()),
+                (
+                    "PGDB_EXTENSIONS".to_string(),
+                    "pgcrypto, pg_trgm".to_string(),
+                ),
+                ("PGDB_LOCALE".to_string(), "en_US.UTF-8".to_string()),
+                ("PGDB_ICU_LOCALE".to_string(), "en-US".to_string()),
+                ("PGDB_DATA_CHECKSUMS".to_string(), "true".to_string()),
                 ("PGDB_USER".to_string(), "ignored".to_string()),
             ])
             .expect("environment must be valid");
@@ -105,5 +275,51 @@ mod tests {
         assert_eq!(environment.force_shutdown_timeout, Some(2));
         assert!(!environment.tests_cleanup());
         assert!(PostgresEnvironment::default().tests_cleanup());
+        assert!(environment.initdb_cache());
+        assert!(!PostgresEnvironment::default().initdb_cache());
+        assert_eq!(environment.shared_dir(), Some("/tmp/shared".into()));
+        assert_eq!(PostgresEnvironment::default().shared_dir(), None);
+        assert!(environment.keep_on_failure());
+        assert_eq!(environment.pg_version.as_deref(), Some(">=15"));
+        assert_eq!(
+            environment
+                .pg_versions()
+                .expect("versions must be valid")
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            ["13", "17"]
+        );
+        assert_eq!(environment.extensions(), ["pgcrypto", "pg_trgm"]);
+        assert_eq!(environment.locale.as_deref(), Some("en_US.UTF-8"));
+        assert_eq!(environment.icu_locale.as_deref(), Some("en-US"));
+        assert!(environment.data_checksums);
+        assert!(PostgresEnvironment::default()
+            .pg_versions()
+            .expect("versions must be valid")
+            .is_empty());
+        assert_eq!(
+            environment.binaries().find_psql().ok(),
+            Some("/opt/pg/bin/psql".into())
+        );
+    }
+
+    #[test]
+    fn binaries_follow_version_requirement() {
+        let installation = match crate::discovery::installations().into_iter().next() {
+            Some(installation) => installation,
+            None => return,
+        };
+        let environment = envy::prefixed("PGDB_")
+            .from_iter::<_, PostgresEnvironment>(vec![(
+                "PGDB_PG_VERSION".to_string(),
+                format!("={}", installation.version()),
+            )])
+            .expect("environment must be valid");
+
+        assert_eq!(
+            environment.binaries().find_psql().ok(),
+            Some(installation.bin_dir().join("psql"))
+        );
     }
 }
@@ -4,28 +4,60 @@
 //! Dropping the [`DbInstance`] will cause the underlying database to be dropped.
 
 use std::{
-    process,
-    sync::{Arc, Mutex, Weak},
+    cmp,
+    collections::BTreeMap,
+    panic::{self, AssertUnwindSafe},
+    path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex, PoisonError, Weak,
+    },
+    thread,
 };
 
 use url::Url;
 
-use crate::{config::PostgresEnvironment, Postgres};
+use crate::{
+    config::PostgresEnvironment,
+    discovery::{self, Installation},
+    Error, PgBinaries, PgVersion, Postgres, PostgresBuilder, SharedServer,
+};
 
 /// A database instance.
 ///
-/// Can be either a local database (with a reference to the running instance) or an external
-/// database URL. Dropping the instance will cause the database to be deleted.
+/// Can be either a local database (with a reference to the running instance), a database on a
+/// shared server or an external database URL. Dropping the instance will cause the database to be
+/// deleted.
+///
+/// Variants and their fields may be added in future releases, so matches need a wildcard arm and
+/// `..` in variant patterns.
 #[derive(Debug)]
+// Instances are few and long-lived, boxing `External`'s binaries is not worth the churn.
+#[allow(clippy::large_enum_variant)]
+#[non_exhaustive]
 pub enum DbInstance {
     /// A local database instance that will be kept alive as long as this DbUrl exists.
+    #[non_exhaustive]
     Local {
         /// A reference to the running Postgres instance where this URL points.
         _arc: Arc<Postgres>,
         /// The actual URL.
         url: Url,
+        /// The template this database was copied from, if any.
+        template: Option<Arc<FixtureTemplate>>,
+    },
+    /// A database on a server shared with other processes, cleaned up on drop.
+    #[non_exhaustive]
+    Shared {
+        /// The database URL.
+        url: Url,
+        /// The template this database was copied from, if any.
+        template: Option<Arc<FixtureTemplate>>,
+        /// A reference to the shared server, stopping it once the last one is dropped.
+        server: Arc<SharedServer>,
     },
     /// An external database URL with admin credentials to clean it up later.
+    #[non_exhaustive]
     External {
         /// The database URL.
         url: Url,
@@ -33,6 +65,10 @@ pub enum DbInstance {
         superuser_url: Url,
         /// Whether to clean up the database and role on drop.
         cleanup: bool,
+        /// Binaries used for cleanup operations.
+        binaries: PgBinaries,
+        /// The template this database was copied from, if any.
+        template: Option<Arc<FixtureTemplate>>,
     },
 }
 
@@ -41,6 +77,7 @@ impl DbInstance {
     pub fn as_str(&self) -> &str {
         match self {
             DbInstance::Local { url, .. } => url.as_str(),
+            DbInstance::Shared { url, .. } => url.as_str(),
             DbInstance::External { url, .. } => url.as_str(),
         }
     }
@@ -49,6 +86,7 @@ impl DbInstance {
     pub fn as_url(&self) -> &Url {
         match self {
             DbInstance::Local { url, .. } => url,
+            DbInstance::Shared { url, .. } => url,
             DbInstance::External { url, .. } => url,
         }
     }
@@ -62,63 +100,150 @@ impl AsRef<str> for DbInstance {
 
 impl Drop for DbInstance {
     fn drop(&mut self) {
-        if let DbInstance::External {
-            url,
-            superuser_url,
-            cleanup,
-        } = self
+        if thread::panicking()
+            && PostgresEnvironment::read().is_ok_and(|environment| environment.keep_on_failure())
         {
-            if !*cleanup {
-                return;
+            eprintln!(
+                "pgdb: keeping fixture database after panic: {}",
+                self.as_str()
+            );
+            return;
+        }
+
+        match self {
+            DbInstance::Local { _arc, url, .. } => {
+                // The last reference shuts down the server, taking the database with it.
+                if Arc::strong_count(_arc) > 1 {
+                    _arc.drop_database_and_role_later(url.clone());
+                }
             }
+            DbInstance::Shared { url, server, .. } => {
+                drop_database_and_role(
+                    &server.binaries().psql_or_default(),
+                    server.superuser_url(),
+                    url,
+                );
+            }
+            DbInstance::External {
+                url,
+                superuser_url,
+                cleanup,
+                binaries,
+                ..
+            } => {
+                if !*cleanup {
+                    return;
+                }
 
-            // Extract database and usernames from the URL
-            let db_name = url.path().trim_start_matches('/');
-            let db_user = url.username();
-
-            // Best effort cleanup - we don't want to panic in [`Drop::drop`].
-            // TODO: Do not use `which` here if a different `psql` binary was configured.
-            let psql_binary = which::which("psql").unwrap_or_else(|_| "psql".into());
-
-            // Helper to run cleanup SQL
-            let run_cleanup_sql = |sql: &str| {
-                let username = superuser_url.username();
-                let password = superuser_url.password().unwrap_or_default();
-
-                let host =
-                    crate::connection_host(superuser_url).unwrap_or_else(|| "localhost".into());
-                let port = crate::connection_port(superuser_url).unwrap_or(5432);
-
-                let _ = process::Command::new(&psql_binary)
-                    .arg("-h")
-                    .arg(host.as_ref())
-                    .arg("-p")
-                    .arg(port.to_string())
-                    .arg("-U")
-                    .arg(username)
-                    .arg("-d")
-                    .arg("postgres")
-                    .arg("-c")
-                    .arg(sql)
-                    .env("PGPASSWORD", password)
-                    .output();
-            };
+                drop_database_and_role(&binaries.psql_or_default(), superuser_url, url);
+            }
+        }
+    }
+}
 
-            // Fixture owners may be dropped before their clients.
-            run_cleanup_sql(&format!(
-                "DROP DATABASE IF EXISTS {} WITH (FORCE);",
-                crate::escape_ident(db_name)
-            ));
-
-            // Drop user
-            run_cleanup_sql(&format!(
-                "DROP ROLE IF EXISTS {};",
-                crate::escape_ident(db_user)
-            ));
+/// Background thread dropping local fixture databases, so that dropping a fixture does not block.
+#[derive(Debug)]
+pub(crate) struct CleanupWorker {
+    /// Queue of database URLs to drop.
+    sender: mpsc::Sender<Url>,
+    /// Set when the server is about to shut down, making queued cleanups pointless.
+    closing: Arc<AtomicBool>,
+    /// Handle of the worker thread.
+    thread: thread::JoinHandle<()>,
+}
+
+impl CleanupWorker {
+    /// Spawns a worker dropping databases and roles through `superuser_url`.
+    pub(crate) fn spawn(psql_binary: path::PathBuf, superuser_url: Url) -> Self {
+        let (sender, receiver) = mpsc::channel::<Url>();
+        let closing = Arc::new(AtomicBool::new(false));
+
+        let worker_closing = closing.clone();
+        let thread = thread::spawn(move || {
+            for url in receiver {
+                if worker_closing.load(Ordering::Relaxed) {
+                    break;
+                }
+                drop_database_and_role(&psql_binary, &superuser_url, &url);
+            }
+        });
+
+        CleanupWorker {
+            sender,
+            closing,
+            thread,
         }
     }
 
-    // TODO: Clean up database if local.
+    /// Queues the database and role referenced by `url` for removal.
+    pub(crate) fn submit(&self, url: Url) {
+        // The worker only exits after `shutdown`, so sending cannot fail.
+        let _ = self.sender.send(url);
+    }
+
+    /// Discards queued cleanups and waits for the one in progress to finish.
+    pub(crate) fn shutdown(self) {
+        self.closing.store(true, Ordering::Relaxed);
+        drop(self.sender);
+        let _ = self.thread.join();
+    }
+}
+
+/// A template database shared by fixtures created through [`db_fixture_from_template`].
+///
+/// The template database and its user are dropped once the last fixture referencing it is gone.
+#[derive(Debug)]
+pub struct FixtureTemplate {
+    /// The local Postgres instance holding the template, kept alive by the template.
+    postgres: Option<Arc<Postgres>>,
+    /// The shared server holding the template, kept alive by the template.
+    shared: Option<Arc<SharedServer>>,
+    /// URL of the template database, with the credentials of its user.
+    url: Url,
+    /// The superuser URL for cleanup operations.
+    superuser_url: Url,
+    /// Whether to clean up the template database and role on drop.
+    cleanup: bool,
+    /// Binaries used to create fixtures and for cleanup operations.
+    binaries: PgBinaries,
+}
+
+impl Drop for FixtureTemplate {
+    fn drop(&mut self) {
+        if self.cleanup {
+            drop_database_and_role(
+                &self.binaries.psql_or_default(),
+                &self.superuser_url,
+                &self.url,
+            );
+        }
+    }
+}
+
+/// Drops the database and role referenced by `url`.
+///
+/// Best effort cleanup - we don't want to panic in [`Drop::drop`], so all errors are ignored.
+fn drop_database_and_role(psql_binary: &path::Path, superuser_url: &Url, url: &Url) {
+    // Extract database and usernames from the URL
+    let db_name = url.path().trim_start_matches('/');
+    let db_user = url.username();
+
+    // Helper to run cleanup SQL
+    let run_cleanup_sql = |sql: &str| {
+        let _ = crate::execute_sql(psql_binary, superuser_url, "postgres", sql);
+    };
+
+    // Fixture owners may be dropped before their clients.
+    run_cleanup_sql(&format!(
+        "DROP DATABASE IF EXISTS {} WITH (FORCE);",
+        crate::escape_ident(db_name)
+    ));
+
+    // Drop user
+    run_cleanup_sql(&format!(
+        "DROP ROLE IF EXISTS {};",
+        crate::escape_ident(db_user)
+    ));
 }
 
 /// A convenience function for regular applications.
@@ -135,41 +260,450 @@ impl Drop for DbInstance {
 /// [`DbInstance`] for details). The database may be shut down and recreated if the last [`DbInstance`] is
 /// dropped during testing, e.g. when parallel tests are not spawned quick enough.
 ///
+/// Set `PGDB_SHARED=true` to share the server with other processes as well, e.g. when running
+/// tests with `cargo nextest`. The first process starts the server and publishes it in a
+/// well-known directory (`PGDB_SHARED_DIR`, by default `pgdb-shared` in `$XDG_RUNTIME_DIR` or
+/// `pgdb-shared-<uid>` in the system's temporary directory), the last process to drop its fixtures
+/// stops it. The directory must be owned by the current user. Databases on a shared server are
+/// dropped along with their [`DbInstance`].
+///
 /// This construction is necessary because `static` variables will not have `Drop` called on them,
 /// without this construction, the spawned Postgres server would not be stopped.
 pub fn db_fixture() -> DbInstance {
     let environment =
         PostgresEnvironment::read().expect("invalid PostgreSQL environment configuration");
+    let binaries = environment.binaries();
 
     // Check for external database URL first
     if let Some(external_url) = crate::parse_external_test_url().expect("invalid PGDB_TESTS_URL") {
-        let url =
-            crate::create_fixture_db(&external_url).expect("failed to create external fixture DB");
+        let url = crate::create_fixture_db(&binaries, &external_url)
+            .expect("failed to create external fixture DB");
+        create_external_extensions(&environment, &binaries, &external_url, &url);
         return DbInstance::External {
             url,
             superuser_url: external_url,
             cleanup: environment.tests_cleanup(),
+            binaries,
+            template: None,
         };
     }
 
-    static DB: Mutex<Weak<Postgres>> = Mutex::new(Weak::new());
+    if let Some(shared_dir) = environment.shared_dir() {
+        let server = shared_server(&fixture_builder(&environment), &shared_dir);
+        let url = crate::create_fixture_db(&binaries, server.superuser_url())
+            .expect("failed to create shared fixture DB");
+        return DbInstance::Shared {
+            url,
+            template: None,
+            server,
+        };
+    }
+
+    let pg = shared_postgres(&environment);
+
+    // Use unified fixture creation for local databases too
+    let url = crate::create_fixture_db(&binaries, pg.superuser_url())
+        .expect("failed to create local fixture DB");
+    DbInstance::Local {
+        _arc: pg,
+        url,
+        template: None,
+    }
+}
+
+/// Like [`db_fixture`], but copies every database from an initialized template.
+///
+/// The first call for a given `name` creates a template database and calls `init` with a URL to
+/// it, e.g. to run migrations. All fixtures created with the same `name` are then created through
+/// `CREATE DATABASE ... TEMPLATE ...`, which is usually much faster than initializing each
+/// database from scratch. Objects created by `init` will be owned by each fixture's user.
+///
+/// `init` must close all of its connections to the template before returning, as PostgreSQL
+/// refuses to copy a database that is in use.
+///
+/// Templates are shared the same way servers are: once the last [`DbInstance`] created from a
+/// template is dropped, the template is dropped as well and will be recreated by the next call.
+pub fn db_fixture_from_template<F>(name: &str, init: F) -> DbInstance
+where
+    F: FnOnce(&Url),
+{
+    let environment =
+        PostgresEnvironment::read().expect("invalid PostgreSQL environment configuration");
+    let binaries = environment.binaries();
+    let external_url = crate::parse_external_test_url().expect("invalid PGDB_TESTS_URL");
+
+    // Every name gets its own slot, so `init` runs without blocking fixtures of other templates. A
+    // panicking `init` leaves its slot empty, the next call for the same name starts over.
+    static TEMPLATES: Mutex<BTreeMap<String, Arc<Mutex<Weak<FixtureTemplate>>>>> =
+        Mutex::new(BTreeMap::new());
 
-    let pg = {
-        let mut guard = DB.lock().expect("lock poisoned");
+    let slot = TEMPLATES
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .entry(name.to_string())
+        .or_default()
+        .clone();
+
+    let template = {
+        let mut guard = slot.lock().unwrap_or_else(PoisonError::into_inner);
         if let Some(arc) = guard.upgrade() {
-            // We still have an instance we can reuse.
+            // The template is still in use and can be reused.
             arc
         } else {
-            let mut builder = Postgres::build();
-            environment.apply(&mut builder);
-            let arc = Arc::new(builder.start().expect("failed to start global postgres DB"));
+            let (pg, shared, superuser_url, cleanup) = match external_url {
+                Some(external_url) => (None, None, external_url, environment.tests_cleanup()),
+                None => match environment.shared_dir() {
+                    Some(shared_dir) => {
+                        let server = shared_server(&fixture_builder(&environment), &shared_dir);
+                        let superuser_url = server.superuser_url().clone();
+                        (None, Some(server), superuser_url, true)
+                    }
+                    None => {
+                        let pg = shared_postgres(&environment);
+                        let superuser_url = pg.superuser_url().clone();
+                        (Some(pg), None, superuser_url, true)
+                    }
+                },
+            };
+            let url = crate::create_template_db(&binaries, &superuser_url)
+                .expect("failed to create fixture template DB");
+            if pg.is_none() && shared.is_none() {
+                create_external_extensions(&environment, &binaries, &superuser_url, &url);
+            }
+            let arc = Arc::new(FixtureTemplate {
+                postgres: pg,
+                shared,
+                url,
+                superuser_url,
+                cleanup,
+                binaries,
+            });
+            init(&arc.url);
             *guard = Arc::downgrade(&arc);
             arc
         }
     };
 
-    // Use unified fixture creation for local databases too
-    let url =
-        crate::create_fixture_db(pg.superuser_url()).expect("failed to create local fixture DB");
-    DbInstance::Local { _arc: pg, url }
+    let url = crate::create_fixture_db_from_template(
+        &template.binaries,
+        &template.superuser_url,
+        &template.url,
+    )
+    .expect("failed to create fixture DB from template");
+    match (&template.postgres, &template.shared) {
+        (Some(pg), _) => DbInstance::Local {
+            _arc: pg.clone(),
+            url,
+            template: Some(template),
+        },
+        (None, Some(server)) => DbInstance::Shared {
+            url,
+            server: server.clone(),
+            template: Some(template),
+        },
+        (None, None) => DbInstance::External {
+            url,
+            superuser_url: template.superuser_url.clone(),
+            cleanup: template.cleanup,
+            binaries: template.binaries.clone(),
+            template: Some(template),
+        },
+    }
+}
+
+/// The shared local Postgres instance used by fixtures.
+static DB: Mutex<Weak<Postgres>> = Mutex::new(Weak::new());
+
+/// Returns the shared local Postgres instance, starting it if necessary.
+fn shared_postgres(environment: &PostgresEnvironment) -> Arc<Postgres> {
+    let mut guard = DB.lock().expect("lock poisoned");
+    if let Some(arc) = guard.upgrade() {
+        // We still have an instance we can reuse.
+        arc
+    } else {
+        let builder = fixture_builder(environment);
+        let arc = Arc::new(builder.start().expect("failed to start global postgres DB"));
+        *guard = Arc::downgrade(&arc);
+        arc
+    }
+}
+
+/// This process' reference to the server shared with other processes.
+static SHARED: Mutex<Weak<SharedServer>> = Mutex::new(Weak::new());
+
+/// Returns the server shared with other processes through `shared_dir`, attaching if necessary.
+fn shared_server(builder: &PostgresBuilder, shared_dir: &path::Path) -> Arc<SharedServer> {
+    let mut guard = SHARED.lock().expect("lock poisoned");
+    if let Some(arc) = guard.upgrade() {
+        // This process is still attached.
+        arc
+    } else {
+        let server = SharedServer::attach(shared_dir, builder)
+            .expect("failed to attach to shared postgres DB");
+        let arc = Arc::new(server);
+        *guard = Arc::downgrade(&arc);
+        arc
+    }
+}
+
+/// Creates the configured extensions in a fixture database on an external server.
+///
+/// Local servers create them in `template1` instead, see [`PostgresBuilder::extension`].
+fn create_external_extensions(
+    environment: &PostgresEnvironment,
+    binaries: &PgBinaries,
+    superuser_url: &Url,
+    url: &Url,
+) {
+    let database = url.path().trim_start_matches('/');
+    crate::create_extensions(binaries, superuser_url, database, &environment.extensions())
+        .expect("failed to create extensions in external fixture DB");
+}
+
+/// Creates the builder for servers started by fixtures.
+fn fixture_builder(environment: &PostgresEnvironment) -> PostgresBuilder {
+    let mut builder = Postgres::build();
+    if environment.initdb_cache() {
+        builder.initdb_cache();
+    }
+    environment.apply(&mut builder);
+    builder
+}
+
+/// Like [`db_fixture`], but creates one database per installed PostgreSQL version.
+///
+/// Uses every installation found through [`discovery`](crate::discovery), one per major version
+/// (9.5 and 9.6 count as different ones), or the newest installation matching each requirement in the comma-separated
+/// `PGDB_PG_VERSIONS`, e.g. `13,14,15,16,17`. Every version gets its own local server, shared by
+/// all fixtures of that version in this process. `PGDB_PORT` is ignored, as every server needs a
+/// port of its own. If `PGDB_TESTS_URL` is set, only a database on that server is returned.
+///
+/// See [`for_each_pg_version`] to run a test against all of them.
+pub fn db_fixture_matrix() -> Vec<(PgVersion, DbInstance)> {
+    let environment =
+        PostgresEnvironment::read().expect("invalid PostgreSQL environment configuration");
+    let binaries = environment.binaries();
+
+    if let Some(external_url) = crate::parse_external_test_url().expect("invalid PGDB_TESTS_URL") {
+        let version = external_server_version(&binaries, &external_url)
+            .expect("failed to query external server version");
+        let url = crate::create_fixture_db(&binaries, &external_url)
+            .expect("failed to create external fixture DB");
+        create_external_extensions(&environment, &binaries, &external_url, &url);
+        return vec![(
+            version,
+            DbInstance::External {
+                url,
+                superuser_url: external_url,
+                cleanup: environment.tests_cleanup(),
+                binaries,
+                template: None,
+            },
+        )];
+    }
+
+    matrix_installations(&environment)
+        .into_iter()
+        .map(|installation| {
+            let pg = versioned_postgres(&environment, &installation);
+            let url = crate::create_fixture_db(&binaries, pg.superuser_url())
+                .expect("failed to create local fixture DB");
+            (
+                pg.server_version(),
+                DbInstance::Local {
+                    _arc: pg,
+                    url,
+                    template: None,
+                },
+            )
+        })
+        .collect()
+}
+
+/// Runs `test` with a fixture database for every PostgreSQL version of [`db_fixture_matrix`].
+///
+/// All versions are tested even if some of them fail. Afterwards, panics with the failures of all
+/// failed versions, each tagged with its server version.
+pub fn for_each_pg_version<F>(mut test: F)
+where
+    F: FnMut(PgVersion, &DbInstance),
+{
+    let mut failures = Vec::new();
+
+    for (version, db) in db_fixture_matrix() {
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| test(version, &db))) {
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "non-string panic payload".to_string());
+            eprintln!("pgdb: test failed on PostgreSQL {}", version);
+            failures.push(format!("PostgreSQL {}: {}", version, message));
+        }
+    }
+
+    if !failures.is_empty() {
+        panic!(
+            "test failed on {} PostgreSQL version(s):\n{}",
+            failures.len(),
+            failures.join("\n")
+        );
+    }
+}
+
+/// Returns the installations to create matrix fixtures on, oldest first.
+fn matrix_installations(environment: &PostgresEnvironment) -> Vec<Installation> {
+    let requirements = environment.pg_versions().expect("invalid PGDB_PG_VERSIONS");
+
+    let mut installations: Vec<Installation> = if requirements.is_empty() {
+        discovery::installations()
+    } else {
+        requirements
+            .iter()
+            .map(|requirement| {
+                discovery::find(requirement).expect("no installation for PGDB_PG_VERSIONS entry")
+            })
+            .collect()
+    };
+
+    // Keep the newest installation of every major version.
+    installations.sort_by_key(|installation| cmp::Reverse(installation.version()));
+    installations.dedup_by_key(|installation| installation.version().release_series());
+    installations.reverse();
+
+    assert!(
+        !installations.is_empty(),
+        "no PostgreSQL installations found"
+    );
+    installations
+}
+
+/// Local Postgres instances used by matrix fixtures, by release series.
+static VERSIONED_DBS: Mutex<BTreeMap<(u32, u32), Weak<Postgres>>> = Mutex::new(BTreeMap::new());
+
+/// Returns the local Postgres instance for `installation`, starting it if necessary.
+fn versioned_postgres(
+    environment: &PostgresEnvironment,
+    installation: &Installation,
+) -> Arc<Postgres> {
+    let mut guard = VERSIONED_DBS.lock().expect("lock poisoned");
+    let series = installation.version().release_series();
+    if let Some(arc) = guard.get(&series).and_then(Weak::upgrade) {
+        // We still have an instance we can reuse.
+        arc
+    } else {
+        let mut binaries = PgBinaries::new();
+        binaries.bin_dir(installation.bin_dir());
+        let mut builder = fixture_builder(environment);
+        // Servers of different versions run side by side and cannot share a configured port.
+        builder.port = None;
+        builder
+            .binaries(binaries)
+            .version(format!("={}", installation.version()));
+        let arc = Arc::new(
+            builder
+                .start()
+                .expect("failed to start versioned postgres DB"),
+        );
+        guard.insert(series, Arc::downgrade(&arc));
+        arc
+    }
+}
+
+/// Queries the version of the server at `superuser_url`.
+fn external_server_version(binaries: &PgBinaries, superuser_url: &Url) -> Result<PgVersion, Error> {
+    let version_num = crate::query_sql(
+        &binaries.psql_or_default(),
+        superuser_url,
+        "postgres",
+        "SHOW server_version_num",
+    )?
+    .first()
+    .and_then(|row| row.get(0))
+    .and_then(|version_num| version_num.parse::<u32>().ok())
+    .ok_or(Error::InvalidPsqlOutput)?;
+
+    Ok(PgVersion::from_version_num(version_num))
+}
+
+/// Async version of [`db_fixture`].
+///
+/// Starts the shared server and creates the fixture database without blocking the async runtime.
+/// Fixtures share the same server as ones created through [`db_fixture`].
+#[cfg(feature = "async")]
+pub async fn db_fixture_async() -> DbInstance {
+    let environment =
+        PostgresEnvironment::read().expect("invalid PostgreSQL environment configuration");
+    let binaries = environment.binaries();
+
+    // Check for external database URL first
+    if let Some(external_url) = crate::parse_external_test_url().expect("invalid PGDB_TESTS_URL") {
+        let url = crate::create_fixture_db_async(&binaries, &external_url)
+            .await
+            .expect("failed to create external fixture DB");
+        let cleanup = environment.tests_cleanup();
+        let (url, superuser_url, binaries) = tokio::task::spawn_blocking(move || {
+            create_external_extensions(&environment, &binaries, &external_url, &url);
+            (url, external_url, binaries)
+        })
+        .await
+        .expect("failed to create extensions in external fixture DB");
+        return DbInstance::External {
+            url,
+            superuser_url,
+            cleanup,
+            binaries,
+            template: None,
+        };
+    }
+
+    if let Some(shared_dir) = environment.shared_dir() {
+        let builder = fixture_builder(&environment);
+        // Attaching may block on other processes starting or stopping the server.
+        let server = tokio::task::spawn_blocking(move || shared_server(&builder, &shared_dir))
+            .await
+            .expect("failed to attach to shared postgres DB");
+        let url = crate::create_fixture_db_async(&binaries, server.superuser_url())
+            .await
+            .expect("failed to create shared fixture DB");
+        return DbInstance::Shared {
+            url,
+            template: None,
+            server,
+        };
+    }
+
+    let pg = shared_postgres_async(&environment).await;
+
+    let url = crate::create_fixture_db_async(&binaries, pg.superuser_url())
+        .await
+        .expect("failed to create local fixture DB");
+    DbInstance::Local {
+        _arc: pg,
+        url,
+        template: None,
+    }
+}
+
+/// Async version of [`shared_postgres`].
+#[cfg(feature = "async")]
+async fn shared_postgres_async(environment: &PostgresEnvironment) -> Arc<Postgres> {
+    // Serializes startup without holding the blocking lock while the server starts.
+    static STARTING: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    let _starting = STARTING.lock().await;
+    if let Some(arc) = DB.lock().expect("lock poisoned").upgrade() {
+        // We still have an instance we can reuse.
+        return arc;
+    }
+
+    let builder = fixture_builder(environment);
+    let arc = Arc::new(
+        builder
+            .start_async()
+            .await
+            .expect("failed to start global postgres DB"),
+    );
+    *DB.lock().expect("lock poisoned") = Arc::downgrade(&arc);
+    arc
 }
@@ -0,0 +1,403 @@
+//! Discovery of installed PostgreSQL versions.
+//!
+//! Installations are searched for in `PGDB_PG_BIN_DIR`, the directory reported by
+//! `pg_config --bindir`, next to the `postgres` binary in `PATH` (which covers Nix profiles and
+//! Homebrew) and in the version-specific directories used by Debian (`/usr/lib/postgresql/*/bin`)
+//! and RHEL (`/usr/pgsql-*/bin`) packages.
+
+use std::{
+    env, fmt, fs, io,
+    path::{Path, PathBuf},
+    process,
+    str::FromStr,
+};
+
+use crate::Error;
+
+/// A PostgreSQL server version.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PgVersion {
+    /// Major version, e.g. `16`.
+    major: u32,
+    /// Minor version, e.g. `4` for PostgreSQL 16.4.
+    minor: u32,
+}
+
+impl PgVersion {
+    /// Creates a version from its components.
+    #[inline]
+    pub fn new(major: u32, minor: u32) -> Self {
+        PgVersion { major, minor }
+    }
+
+    /// Returns the major version.
+    #[inline]
+    pub fn major(&self) -> u32 {
+        self.major
+    }
+
+    /// Returns the minor version.
+    #[inline]
+    pub fn minor(&self) -> u32 {
+        self.minor
+    }
+
+    /// Determines the version of a `postgres` binary by running `postgres --version`.
+    pub(crate) fn of_binary(postgres_binary: &Path) -> Result<Self, Error> {
+        let output = process::Command::new(postgres_binary)
+            .arg("--version")
+            .stdin(process::Stdio::null())
+            .stderr(process::Stdio::null())
+            .output()
+            .map_err(Error::PostgresVersion)?;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if !output.status.success() {
+            return Err(Error::PostgresVersion(io::Error::other(format!(
+                "`postgres --version` exited with {}",
+                output.status
+            ))));
+        }
+
+        Self::parse_version_output(&stdout).ok_or_else(|| {
+            Error::PostgresVersion(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unexpected `postgres --version` output {:?}", stdout.trim()),
+            ))
+        })
+    }
+
+    /// Converts a `server_version_num`, e.g. `160004` for 16.4.
+    pub(crate) fn from_version_num(version_num: u32) -> Self {
+        // Before 10, the major version consisted of two parts, e.g. `90624` is 9.6.24.
+        if version_num < 100000 {
+            PgVersion::new(version_num / 10000, (version_num / 100) % 100)
+        } else {
+            PgVersion::new(version_num / 10000, version_num % 10000)
+        }
+    }
+
+    /// Returns the release series, e.g. `(16, 0)` for 16.4 and `(9, 6)` for 9.6.
+    ///
+    /// Before 10, the minor component is part of the major version, so 9.5 and 9.6 differ.
+    pub(crate) fn release_series(&self) -> (u32, u32) {
+        if self.major < 10 {
+            (self.major, self.minor)
+        } else {
+            (self.major, 0)
+        }
+    }
+
+    /// Parses output like `postgres (PostgreSQL) 16.4 (Debian 16.4-1)`.
+    fn parse_version_output(output: &str) -> Option<Self> {
+        let version = output
+            .split_whitespace()
+            .find(|word| word.starts_with(|c: char| c.is_ascii_digit()))?;
+
+        // Development versions are reported as e.g. `17beta1` or `17devel`.
+        let mut parts = version.split('.');
+        let major = leading_number(parts.next()?)?;
+        let minor = parts.next().and_then(leading_number).unwrap_or(0);
+        Some(PgVersion { major, minor })
+    }
+}
+
+impl fmt::Display for PgVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// Parses the number at the start of `s`.
+fn leading_number(s: &str) -> Option<u32> {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    s[..end].parse().ok()
+}
+
+/// A requirement on the PostgreSQL version, e.g. `16`, `>=15` or `>=14, <17`.
+///
+/// Comma-separated comparisons must all match. A version without a minor component only
+/// compares major versions, so `16` matches every 16.x release and `>15` does not match 15.4.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VersionReq {
+    /// The original requirement, for messages.
+    source: String,
+    /// All comparisons a version must satisfy.
+    comparators: Vec<Comparator>,
+}
+
+/// A single comparison inside a [`VersionReq`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Comparator {
+    /// How to compare.
+    op: Op,
+    /// Major version to compare to.
+    major: u32,
+    /// Minor version to compare to, if given.
+    minor: Option<u32>,
+}
+
+/// Comparison operator of a [`Comparator`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+impl VersionReq {
+    /// Returns whether `version` satisfies this requirement.
+    pub fn matches(&self, version: PgVersion) -> bool {
+        self.comparators
+            .iter()
+            .all(|comparator| comparator.matches(version))
+    }
+}
+
+impl Comparator {
+    /// Returns whether `version` satisfies this comparison.
+    fn matches(&self, version: PgVersion) -> bool {
+        let ordering = match self.minor {
+            Some(minor) => (version.major, version.minor).cmp(&(self.major, minor)),
+            None => version.major.cmp(&self.major),
+        };
+
+        match self.op {
+            Op::Eq => ordering.is_eq(),
+            Op::Gt => ordering.is_gt(),
+            Op::Ge => ordering.is_ge(),
+            Op::Lt => ordering.is_lt(),
+            Op::Le => ordering.is_le(),
+        }
+    }
+}
+
+impl FromStr for VersionReq {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidVersionRequirement(s.to_string());
+
+        let comparators = s
+            .split(',')
+            .map(|part| {
+                let part = part.trim();
+                let (op, version) = [
+                    (">=", Op::Ge),
+                    ("<=", Op::Le),
+                    (">", Op::Gt),
+                    ("<", Op::Lt),
+                    ("=", Op::Eq),
+                ]
+                .iter()
+                .find_map(|(prefix, op)| part.strip_prefix(prefix).map(|rest| (*op, rest)))
+                .unwrap_or((Op::Eq, part));
+
+                let mut numbers = version.trim().split('.');
+                let major = numbers
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .ok_or_else(invalid)?;
+                let minor = numbers
+                    .next()
+                    .map(|n| n.parse().map_err(|_| invalid()))
+                    .transpose()?;
+                if numbers.next().is_some() {
+                    return Err(invalid());
+                }
+
+                Ok(Comparator { op, major, minor })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(VersionReq {
+            source: s.trim().to_string(),
+            comparators,
+        })
+    }
+}
+
+impl fmt::Display for VersionReq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// An installed PostgreSQL version.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Installation {
+    /// Directory containing `postgres`, `initdb` and the other binaries.
+    bin_dir: PathBuf,
+    /// Version of the `postgres` binary.
+    version: PgVersion,
+}
+
+impl Installation {
+    /// Returns the directory containing the binaries.
+    #[inline]
+    pub fn bin_dir(&self) -> &Path {
+        &self.bin_dir
+    }
+
+    /// Returns the server version.
+    #[inline]
+    pub fn version(&self) -> PgVersion {
+        self.version
+    }
+}
+
+/// Returns all PostgreSQL installations found on this system.
+///
+/// Installations are returned in the order they were found, each directory at most once.
+pub fn installations() -> Vec<Installation> {
+    let mut installations: Vec<Installation> = Vec::new();
+    let mut seen = Vec::new();
+
+    for bin_dir in candidate_dirs() {
+        let postgres_binary = bin_dir.join("postgres");
+        let canonical = match fs::canonicalize(&postgres_binary) {
+            Ok(canonical) => canonical,
+            Err(_) => continue,
+        };
+        if seen.contains(&canonical) {
+            continue;
+        }
+        seen.push(canonical);
+
+        if let Ok(version) = PgVersion::of_binary(&postgres_binary) {
+            installations.push(Installation { bin_dir, version });
+        }
+    }
+
+    installations
+}
+
+/// Returns the newest installation satisfying `requirement`.
+pub fn find(requirement: &VersionReq) -> Result<Installation, Error> {
+    installations()
+        .into_iter()
+        .filter(|installation| requirement.matches(installation.version))
+        // `max_by_key` returns the last maximum, prefer the first one found instead.
+        .rev()
+        .max_by_key(Installation::version)
+        .ok_or_else(|| Error::NoMatchingPostgres(requirement.to_string()))
+}
+
+/// Returns all directories that might contain PostgreSQL binaries, most specific first.
+fn candidate_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+
+    if let Some(bin_dir) = env::var_os("PGDB_PG_BIN_DIR") {
+        dirs.push(PathBuf::from(bin_dir));
+    }
+
+    if let Ok(output) = process::Command::new("pg_config")
+        .arg("--bindir")
+        .stderr(process::Stdio::null())
+        .output()
+    {
+        if output.status.success() {
+            let bin_dir = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            if !bin_dir.is_empty() {
+                dirs.push(PathBuf::from(bin_dir));
+            }
+        }
+    }
+
+    if let Some(bin_dir) = which::which("postgres")
+        .ok()
+        .and_then(|postgres| postgres.parent().map(Path::to_path_buf))
+    {
+        dirs.push(bin_dir);
+    }
+
+    // Debian and Ubuntu: /usr/lib/postgresql/<major>/bin
+    dirs.extend(
+        subdirs(Path::new("/usr/lib/postgresql"), |_| true)
+            .into_iter()
+            .map(|dir| dir.join("bin")),
+    );
+
+    // RHEL and Fedora (PGDG packages): /usr/pgsql-<major>/bin
+    dirs.extend(
+        subdirs(Path::new("/usr"), |name| name.starts_with("pgsql-"))
+            .into_iter()
+            .map(|dir| dir.join("bin")),
+    );
+
+    dirs
+}
+
+/// Returns the subdirectories of `dir` whose name satisfies `filter`, sorted by name.
+fn subdirs(dir: &Path, filter: impl Fn(&str) -> bool) -> Vec<PathBuf> {
+    let mut subdirs: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_name().to_str().is_some_and(&filter))
+            .map(|entry| entry.path())
+            .collect(),
+        Err(_) => return Vec::new(),
+    };
+    subdirs.sort();
+    subdirs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PgVersion, VersionReq};
+
+    #[test]
+    fn parses_version_output() {
+        assert_eq!(
+            PgVersion::parse_version_output("postgres (PostgreSQL) 16.4 (Debian 16.4-1.pgdg120+1)"),
+            Some(PgVersion::new(16, 4))
+        );
+        assert_eq!(
+            PgVersion::parse_version_output("postgres (PostgreSQL) 17beta1"),
+            Some(PgVersion::new(17, 0))
+        );
+        assert_eq!(
+            PgVersion::parse_version_output("postgres (PostgreSQL) 9.6.24"),
+            Some(PgVersion::new(9, 6))
+        );
+        assert_eq!(PgVersion::parse_version_output("postgres"), None);
+    }
+
+    #[test]
+    fn converts_version_nums() {
+        assert_eq!(PgVersion::from_version_num(160004), PgVersion::new(16, 4));
+        assert_eq!(PgVersion::from_version_num(100023), PgVersion::new(10, 23));
+        assert_eq!(PgVersion::from_version_num(90624), PgVersion::new(9, 6));
+    }
+
+    #[test]
+    fn release_series_split_pre_10_versions() {
+        assert_eq!(PgVersion::new(16, 4).release_series(), (16, 0));
+        assert_eq!(PgVersion::new(9, 6).release_series(), (9, 6));
+        assert_ne!(
+            PgVersion::new(9, 5).release_series(),
+            PgVersion::new(9, 6).release_series()
+        );
+    }
+
+    #[test]
+    fn version_requirements_match() {
+        let req = |s: &str| s.parse::<VersionReq>().expect("requirement must be valid");
+        let v15_4 = PgVersion::new(15, 4);
+
+        assert!(req("15").matches(v15_4));
+        assert!(req(">=15").matches(v15_4));
+        assert!(!req(">15").matches(v15_4));
+        assert!(req(">15.2").matches(v15_4));
+        assert!(req(">=14, <16").matches(v15_4));
+        assert!(!req(">=14, <15").matches(v15_4));
+        assert!(req("=15.4").matches(v15_4));
+        assert!(!req("<= 14").matches(v15_4));
+
+        assert!("".parse::<VersionReq>().is_err());
+        assert!(">=fifteen".parse::<VersionReq>().is_err());
+        assert!("15.4.1".parse::<VersionReq>().is_err());
+    }
+}
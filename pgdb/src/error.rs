@@ -2,7 +2,7 @@
 //!
 //! Contains all errors produced by any part of this crate.
 
-use std::{io, process};
+use std::{fmt, io, path, process};
 
 use thiserror::Error;
 
@@ -20,35 +20,285 @@ pub enum Error {
     /// Failed to find the `postgres` binary.
     #[error("could not find `psql` binary")]
     FindPsql(which::Error),
+    /// Failed to find the `pg_basebackup` binary.
+    #[error("could not find `pg_basebackup` binary")]
+    FindPgBasebackup(which::Error),
+    /// The version requirement could not be parsed.
+    #[error("invalid PostgreSQL version requirement {0:?}")]
+    InvalidVersionRequirement(String),
+    /// No installed PostgreSQL version satisfies the requirement.
+    #[error("no PostgreSQL installation matching {0:?} found")]
+    NoMatchingPostgres(String),
+    /// The configured `postgres` binary does not satisfy the version requirement.
+    #[error("`postgres` version {found} does not match {required:?}")]
+    PostgresVersionMismatch {
+        /// The version requirement.
+        required: String,
+        /// The version of the configured binary.
+        found: crate::PgVersion,
+    },
+    /// A requested extension is not installed on the server.
+    #[error(
+        "extension {name:?} is not available, available extensions: {}",
+        available.join(", ")
+    )]
+    ExtensionUnavailable {
+        /// Name of the missing extension.
+        name: String,
+        /// All extensions listed in `pg_available_extensions`.
+        available: Vec<String>,
+    },
+    /// The version of the `postgres` binary could not be determined.
+    #[error("could not determine `postgres` version")]
+    PostgresVersion(#[source] io::Error),
     /// Could not create the temporary directory.
     #[error("could not create temporary directory for database")]
     CreateDatabaseDir(io::Error),
     /// Could not write the temporary password to a file.
     #[error("error writing temporary password")]
     WriteTemporaryPw(io::Error),
+    /// A configuration file to include could not be found.
+    #[error("could not find configuration file {}", path.display())]
+    FindConfigFile {
+        /// The configured path.
+        path: path::PathBuf,
+        /// The underlying error.
+        #[source]
+        source: io::Error,
+    },
+    /// Could not include the configuration files in `postgresql.conf`.
+    #[error("error writing configuration file includes")]
+    WriteConfigIncludes(#[source] io::Error),
+    /// Could not write the generated `pg_hba.conf`.
+    #[error("error writing pg_hba.conf")]
+    WriteHbaFile(#[source] io::Error),
+    /// A persistent cluster was requested without a data directory.
+    #[error("persistent mode requires a data directory")]
+    PersistentWithoutDataDir,
+    /// Could not read the superuser password stored in a persistent data directory.
+    #[error("could not read stored superuser password")]
+    ReadSuperuserPw(io::Error),
+    /// Could not store the superuser password in a persistent data directory.
+    #[error("could not store superuser password")]
+    StoreSuperuserPw(io::Error),
+    /// Attaching to or detaching from a server shared across processes failed.
+    #[error("failed to coordinate shared server")]
+    SharedServer(#[source] io::Error),
+    /// The directory coordinating a shared server belongs to another user.
+    #[error("shared server directory {} is not owned by the current user", .0.display())]
+    UntrustedSharedDir(path::PathBuf),
     /// Starting `initdb` failed.
     #[error("failed to run `initdb`")]
     RunInitDb(io::Error),
     /// Running `initdb` was not successful.
     #[error("`initdb` exited with status {}", 0)]
     InitDbFailed(process::ExitStatus),
+    /// The server log file could not be opened.
+    #[error("could not open server log")]
+    OpenLog(#[source] io::Error),
     /// Postgres could not be launched.
     #[error("failed to launch `postgres`")]
     LaunchPostgres(io::Error),
+    /// Starting `pg_basebackup` failed.
+    #[error("failed to run `pg_basebackup`")]
+    RunPgBasebackup(#[source] io::Error),
+    /// Running `pg_basebackup` was not successful.
+    #[error("`pg_basebackup` exited with status {0}")]
+    PgBasebackupFailed(process::ExitStatus),
+    /// The server is not a replica.
+    #[error("server is not a replica")]
+    NotAReplica,
+    /// A replica did not replay the changes of its primary in time.
+    #[error("replica did not replay the primary's changes in time")]
+    ReplayTimeout,
+    /// A replica could not be promoted.
+    #[error("replica could not be promoted")]
+    PromoteFailed,
     /// Postgres was launched but did not become ready in time.
     #[error("postgres did not become ready in time")]
     StartupTimeout,
+    /// Postgres could not be stopped to take or restore a snapshot.
+    #[error("failed to stop `postgres`")]
+    StopPostgres(#[source] process_guard::ShutdownError),
+    /// Copying a data directory for a snapshot failed.
+    #[error("failed to copy data directory")]
+    Snapshot(#[source] io::Error),
+    /// The given snapshot name cannot be used as a directory name.
+    #[error("invalid snapshot name {0:?}")]
+    InvalidSnapshotName(String),
+    /// No snapshot with the given name exists.
+    #[error("no snapshot named {0:?}")]
+    UnknownSnapshot(String),
     /// `psql` could not be launched.
     #[error("failed to run `psql`")]
     RunPsql(io::Error),
     /// Running `psql` returned an error.
-    #[error("`psql` exited with status {}", 0)]
-    PsqlFailed(process::ExitStatus),
+    #[error("`psql` failed: {0}")]
+    PsqlFailed(PsqlFailure),
+    /// The output of `psql` could not be parsed.
+    #[error("could not parse `psql` output")]
+    InvalidPsqlOutput,
+    /// Executing SQL through the native client failed.
+    #[cfg(feature = "native-client")]
+    #[error("failed to execute SQL")]
+    NativeClient(#[source] postgres::Error),
+    /// The native client could not set up TLS as requested by the connection URL.
+    #[cfg(feature = "native-client")]
+    #[error("failed to set up TLS for native client")]
+    NativeClientTls(#[source] openssl::error::ErrorStack),
+    /// The connection URL requests an `sslmode` the native client does not know.
+    #[cfg(feature = "native-client")]
+    #[error("unsupported sslmode {0:?}")]
+    UnsupportedSslMode(String),
+    /// Failed to generate TLS certificates.
+    #[cfg(feature = "tls")]
+    #[error("failed to generate TLS certificates")]
+    GenerateCertificate(#[source] rcgen::Error),
+    /// Failed to read or write TLS certificates.
+    #[cfg(feature = "tls")]
+    #[error("failed to access TLS certificate files")]
+    CertificateFiles(#[source] io::Error),
     /// Invalid external test URL.
     #[error("invalid PGDB_TESTS_URL")]
     InvalidExternalUrl(#[source] ExternalUrlError),
 }
 
+/// Details about a failed `psql` invocation.
+///
+/// Created from the output `psql` wrote to stderr, which is captured instead of being passed
+/// through.
+#[derive(Debug)]
+pub struct PsqlFailure {
+    /// Exit status of `psql`.
+    status: process::ExitStatus,
+    /// The primary error message.
+    message: String,
+    /// The SQLSTATE error code, if reported.
+    sqlstate: Option<String>,
+    /// The input file the error occurred in, if any.
+    file: Option<path::PathBuf>,
+    /// The line within [`PsqlFailure::file`] the error occurred in.
+    line: Option<u32>,
+    /// Everything `psql` wrote to stderr.
+    stderr: String,
+}
+
+impl PsqlFailure {
+    /// Parses the stderr output of a failed `psql` invocation.
+    ///
+    /// Expects `psql` to have been run with `VERBOSITY=verbose` to find the SQLSTATE.
+    pub(crate) fn from_output(status: process::ExitStatus, stderr: &[u8]) -> Self {
+        let stderr = String::from_utf8_lossy(stderr).into_owned();
+
+        let mut failure = PsqlFailure {
+            status,
+            message: stderr
+                .lines()
+                .rev()
+                .find(|line| !line.trim().is_empty())
+                .unwrap_or_default()
+                .trim()
+                .to_string(),
+            sqlstate: None,
+            file: None,
+            line: None,
+            stderr: String::new(),
+        };
+
+        for line in stderr.lines() {
+            let Some((prefix, severity, rest)) = ["ERROR:  ", "FATAL:  ", "PANIC:  "]
+                .iter()
+                .find_map(|severity| {
+                    line.split_once(severity)
+                        .map(|(prefix, rest)| (prefix, severity.trim_end(), rest))
+                })
+            else {
+                continue;
+            };
+
+            // Verbose messages start with a five character SQLSTATE code.
+            let (sqlstate, message) = match rest.split_once(": ") {
+                Some((code, message))
+                    if code.len() == 5
+                        && code
+                            .chars()
+                            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit()) =>
+                {
+                    (Some(code.to_string()), message)
+                }
+                _ => (None, rest),
+            };
+            failure.message = format!("{severity} {message}");
+            failure.sqlstate = sqlstate;
+
+            // Errors in input files are prefixed with `psql:FILE:LINE: `.
+            if let Some((file, line)) = prefix
+                .strip_prefix("psql:")
+                .and_then(|location| location.strip_suffix(": "))
+                .and_then(|location| location.rsplit_once(':'))
+            {
+                if let Ok(line) = line.parse() {
+                    failure.file = Some(file.into());
+                    failure.line = Some(line);
+                }
+            }
+            break;
+        }
+
+        failure.stderr = stderr;
+        failure
+    }
+
+    /// Returns the exit status of `psql`.
+    #[inline]
+    pub fn status(&self) -> process::ExitStatus {
+        self.status
+    }
+
+    /// Returns the primary error message, including its severity.
+    #[inline]
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Returns the SQLSTATE error code, if one was reported.
+    #[inline]
+    pub fn sqlstate(&self) -> Option<&str> {
+        self.sqlstate.as_deref()
+    }
+
+    /// Returns the input file the error occurred in, if any.
+    #[inline]
+    pub fn file(&self) -> Option<&path::Path> {
+        self.file.as_deref()
+    }
+
+    /// Returns the line of the input file the error occurred in, if any.
+    #[inline]
+    pub fn line(&self) -> Option<u32> {
+        self.line
+    }
+
+    /// Returns everything `psql` wrote to stderr.
+    #[inline]
+    pub fn stderr(&self) -> &str {
+        &self.stderr
+    }
+}
+
+impl fmt::Display for PsqlFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(sqlstate) = &self.sqlstate {
+            write!(f, "[{sqlstate}] ")?;
+        }
+        f.write_str(&self.message)?;
+        if let (Some(file), Some(line)) = (&self.file, self.line) {
+            write!(f, " at {}:{line}", file.display())?;
+        }
+        write!(f, " ({})", self.status)
+    }
+}
+
 /// Errors that can occur when parsing an external database URL.
 #[derive(Debug, Error)]
 pub enum ExternalUrlError {
@@ -65,3 +315,43 @@ pub enum ExternalUrlError {
     #[error("must include a username")]
     MissingUsername,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::process::ExitStatusExt, process::ExitStatus};
+
+    use super::PsqlFailure;
+
+    #[test]
+    fn parses_verbose_psql_errors() {
+        let failure = PsqlFailure::from_output(
+            ExitStatus::from_raw(3 << 8),
+            b"psql:/tmp/schema.sql:3: ERROR:  42P01: relation \"missing\" does not exist\n\
+              LINE 1: SELECT * FROM missing;\n\
+              LOCATION:  parserOpenTable, parse_relation.c:1392\n",
+        );
+
+        assert_eq!(
+            failure.message(),
+            "ERROR: relation \"missing\" does not exist"
+        );
+        assert_eq!(failure.sqlstate(), Some("42P01"));
+        assert_eq!(
+            failure.file(),
+            Some(std::path::Path::new("/tmp/schema.sql"))
+        );
+        assert_eq!(failure.line(), Some(3));
+        assert_eq!(
+            failure.to_string(),
+            "[42P01] ERROR: relation \"missing\" does not exist at /tmp/schema.sql:3 (exit status: 3)"
+        );
+
+        let failure = PsqlFailure::from_output(
+            ExitStatus::from_raw(2 << 8),
+            b"psql: error: could not connect\n",
+        );
+        assert_eq!(failure.message(), "psql: error: could not connect");
+        assert_eq!(failure.sqlstate(), None);
+        assert_eq!(failure.file(), None);
+    }
+}
@@ -0,0 +1,73 @@
+//! On-disk cache of freshly initialized clusters.
+//!
+//! Running `initdb` usually dominates the startup time of a server. Clusters initialized with the
+//! same `initdb` binary and arguments are identical apart from the superuser password, so one is
+//! kept per combination and copied instead of running `initdb` again.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    env, ffi, fs,
+    hash::{Hash, Hasher},
+    io, path, process,
+};
+
+/// Returns the default cache directory, `$XDG_CACHE_HOME/pgdb` or `$HOME/.cache/pgdb`.
+pub(crate) fn default_dir() -> Option<path::PathBuf> {
+    env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(path::PathBuf::from)
+        .or_else(|| {
+            env::var_os("HOME")
+                .filter(|dir| !dir.is_empty())
+                .map(|home| path::Path::new(&home).join(".cache"))
+        })
+        .map(|dir| dir.join("pgdb"))
+}
+
+/// Returns the directory for clusters created by `initdb_binary` with the given arguments.
+///
+/// The key includes the output of `initdb --version`, so upgrading PostgreSQL in place does not
+/// reuse stale clusters.
+pub(crate) fn entry_dir(
+    cache_dir: &path::Path,
+    initdb_binary: &path::Path,
+    initdb_args: &[ffi::OsString],
+) -> io::Result<path::PathBuf> {
+    let output = process::Command::new(initdb_binary)
+        .arg("--version")
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "`initdb --version` exited with status {}",
+            output.status
+        )));
+    }
+
+    // Not stable across Rust releases, which at worst causes a cache miss.
+    let mut hasher = DefaultHasher::new();
+    output.stdout.hash(&mut hasher);
+    initdb_binary.hash(&mut hasher);
+    initdb_args.hash(&mut hasher);
+
+    Ok(cache_dir.join(format!("{:016x}", hasher.finish())))
+}
+
+/// Creates a staging directory next to the cache entries, to copy a new cluster into.
+pub(crate) fn staging_dir(cache_dir: &path::Path) -> io::Result<tempfile::TempDir> {
+    fs::create_dir_all(cache_dir)?;
+    tempfile::Builder::new()
+        .prefix(".staging-")
+        .tempdir_in(cache_dir)
+}
+
+/// Moves a fully initialized cluster into the cache.
+///
+/// Concurrent processes may populate the same entry; the first one to finish wins and the other
+/// clusters are discarded along with their staging directories.
+pub(crate) fn insert(cluster: &path::Path, entry_dir: &path::Path) -> io::Result<()> {
+    match fs::rename(cluster, entry_dir) {
+        Ok(()) => Ok(()),
+        Err(_) if entry_dir.is_dir() => Ok(()),
+        Err(err) => Err(err),
+    }
+}
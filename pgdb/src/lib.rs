@@ -1,26 +1,67 @@
 #![doc = include_str!("../README.md")]
 
+mod auth;
+mod binaries;
 pub mod config;
 mod db_instance;
+pub mod discovery;
 mod error;
+mod initdb_cache;
+#[cfg(feature = "native-client")]
+mod native;
+mod row;
+mod shared;
+#[cfg(feature = "tls")]
+mod tls;
 
 use std::{
     borrow::Cow,
-    env, fs, io,
+    env, ffi, fs, io,
     net::TcpListener,
-    path, process, thread,
+    path, process,
+    sync::Mutex,
+    thread,
     time::{Duration, Instant},
 };
 
-pub use db_instance::{db_fixture, DbInstance};
-pub use error::{Error, ExternalUrlError};
+pub use auth::AuthMethod;
+pub use binaries::PgBinaries;
+#[cfg(feature = "async")]
+pub use db_instance::db_fixture_async;
+pub use db_instance::{
+    db_fixture, db_fixture_from_template, db_fixture_matrix, for_each_pg_version, DbInstance,
+    FixtureTemplate,
+};
+pub use discovery::PgVersion;
+pub use error::{Error, ExternalUrlError, PsqlFailure};
 use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
 use process_guard::{ProcessGuard, ShutdownPolicy, Signal, DEFAULT_FORCE_TIME};
+pub use row::Row;
+pub use shared::SharedServer;
 use url::Url;
 
 /// Default PostgreSQL port and Unix socket suffix.
 const DEFAULT_POSTGRES_PORT: u16 = 5432;
 
+/// Name of the server log file inside the temporary directory.
+const LOG_FILE: &str = "postgres.log";
+
+/// Extensions that only work if their library is in `shared_preload_libraries`.
+const PRELOAD_EXTENSIONS: &[&str] = &[
+    "citus",
+    "pg_cron",
+    "pg_stat_statements",
+    "pgaudit",
+    "timescaledb",
+];
+
+/// Name of the file inside the data directory including the files from
+/// [`PostgresBuilder::config_file`].
+const CONFIG_INCLUDES_FILE: &str = "pgdb-includes.conf";
+
+/// Name of the file storing the superuser password inside persistent data directories.
+const STORED_SUPERUSER_PW_FILE: &str = "pgdb-superuser-pw";
+
 /// Returns the connection host represented by a PostgreSQL URL.
 pub fn connection_host(url: &Url) -> Option<Cow<'_, str>> {
     if let Some((_, host)) = url.query_pairs().find(|(key, _)| key == "host") {
@@ -40,38 +81,298 @@ pub fn connection_port(url: &Url) -> Option<u16> {
 }
 
 /// Executes SQL using psql with the given connection parameters.
-pub fn run_psql_command(superuser_url: &Url, database: &str, sql: &str) -> Result<(), Error> {
-    // TODO: Do not use which, allow passing in.
-    let psql_binary = which::which("psql").unwrap_or_else(|_| "psql".into());
-    let username = superuser_url.username();
-    let password = superuser_url.password().unwrap_or_default();
-    let host = connection_host(superuser_url).expect("URL must have a host");
-    let port = connection_port(superuser_url).unwrap_or(5432);
-
-    let status = process::Command::new(&psql_binary)
+///
+/// With the `native-client` feature enabled, the SQL is sent directly to the server instead.
+pub fn run_psql_command(
+    binaries: &PgBinaries,
+    superuser_url: &Url,
+    database: &str,
+    sql: &str,
+) -> Result<(), Error> {
+    execute_sql(&binaries.psql_or_default(), superuser_url, database, sql)
+}
+
+/// Runs the SQL commands from an input file using psql with the given connection parameters.
+///
+/// Stops at the first failing statement, like [`PostgresClient::load_sql`].
+pub fn load_psql_file<P: AsRef<path::Path>>(
+    binaries: &PgBinaries,
+    url: &Url,
+    database: &str,
+    filename: P,
+) -> Result<(), Error> {
+    load_sql_file(
+        &binaries.psql_or_default(),
+        url,
+        database,
+        filename.as_ref(),
+    )
+}
+
+/// Runs the SQL commands from `filename` through `psql`, stopping at the first error.
+fn load_sql_file(
+    psql_binary: &path::Path,
+    url: &Url,
+    database: &str,
+    filename: &path::Path,
+) -> Result<(), Error> {
+    run_psql(
+        psql_command(psql_binary, url, database)
+            .args(["-v", "ON_ERROR_STOP=1"])
+            .arg("-f")
+            .arg(filename),
+    )?;
+
+    Ok(())
+}
+
+/// Creates a `psql` command connecting to `database`, using the credentials from `url`.
+fn psql_command(psql_binary: &path::Path, url: &Url, database: &str) -> process::Command {
+    let mut command = client_command(psql_binary, url);
+    command.arg("-d").arg(database);
+    command
+}
+
+/// Creates a command for a libpq-based client like `psql`, using the server and credentials from
+/// `url`.
+fn client_command(binary: &path::Path, url: &Url) -> process::Command {
+    let username = url.username();
+    let password = url.password().unwrap_or_default();
+    let host = connection_host(url).expect("URL must have a host");
+    let port = connection_port(url).unwrap_or(5432);
+
+    let mut command = process::Command::new(binary);
+    command
         .arg("-h")
         .arg(host.as_ref())
         .arg("-p")
         .arg(port.to_string())
         .arg("-U")
         .arg(username)
-        .arg("-d")
-        .arg(database)
-        .arg("-c")
-        .arg(sql)
-        .env("PGPASSWORD", password)
-        .status()
+        .env("PGPASSWORD", password);
+    for (key, value) in url.query_pairs() {
+        match key.as_ref() {
+            "sslmode" => command.env("PGSSLMODE", value.as_ref()),
+            "sslrootcert" => command.env("PGSSLROOTCERT", value.as_ref()),
+            "sslcert" => command.env("PGSSLCERT", value.as_ref()),
+            "sslkey" => command.env("PGSSLKEY", value.as_ref()),
+            _ => continue,
+        };
+    }
+    command
+}
+
+/// Executes SQL on `database` through `psql`, using the credentials from `url`.
+#[cfg(not(feature = "native-client"))]
+fn execute_sql(
+    psql_binary: &path::Path,
+    url: &Url,
+    database: &str,
+    sql: &str,
+) -> Result<(), Error> {
+    run_psql(psql_command(psql_binary, url, database).arg("-c").arg(sql))?;
+
+    Ok(())
+}
+
+/// Executes SQL on `database` through the native client, using the credentials from `url`.
+#[cfg(feature = "native-client")]
+fn execute_sql(
+    _psql_binary: &path::Path,
+    url: &Url,
+    database: &str,
+    sql: &str,
+) -> Result<(), Error> {
+    native::batch_execute(url, database, sql)
+}
+
+/// Asynchronously executes SQL on `database` through `psql`, using the credentials from `url`.
+#[cfg(all(feature = "async", not(feature = "native-client")))]
+async fn execute_sql_async(
+    psql_binary: &path::Path,
+    url: &Url,
+    database: &str,
+    sql: &str,
+) -> Result<(), Error> {
+    let mut command = psql_command(psql_binary, url, database);
+    command.arg("-c").arg(sql);
+    run_psql_async(command).await?;
+
+    Ok(())
+}
+
+/// Asynchronously executes SQL on `database` through the native client.
+///
+/// The native client is blocking, so it is run on a thread reserved for blocking operations.
+#[cfg(all(feature = "async", feature = "native-client"))]
+async fn execute_sql_async(
+    _psql_binary: &path::Path,
+    url: &Url,
+    database: &str,
+    sql: &str,
+) -> Result<(), Error> {
+    let (url, database, sql) = (url.clone(), database.to_string(), sql.to_string());
+    tokio::task::spawn_blocking(move || native::batch_execute(&url, &database, &sql))
+        .await
+        .expect("native SQL execution panicked")
+}
+
+/// Runs a `psql` command, capturing its output.
+///
+/// Returns everything written to stdout on success. Errors are reported with `VERBOSITY=verbose`,
+/// so their SQLSTATE can be extracted.
+fn run_psql(command: &mut process::Command) -> Result<Vec<u8>, Error> {
+    let output = command
+        .args(["-v", "VERBOSITY=verbose"])
+        .output()
+        .map_err(Error::RunPsql)?;
+
+    psql_stdout(output)
+}
+
+/// Asynchronously runs a `psql` command, capturing its output.
+///
+/// See [`run_psql`] for details.
+#[cfg(all(feature = "async", not(feature = "native-client")))]
+async fn run_psql_async(command: process::Command) -> Result<Vec<u8>, Error> {
+    let output = tokio::process::Command::from(command)
+        .args(["-v", "VERBOSITY=verbose"])
+        .output()
+        .await
         .map_err(Error::RunPsql)?;
 
-    if !status.success() {
-        return Err(Error::PsqlFailed(status));
+    psql_stdout(output)
+}
+
+/// Returns the stdout of a finished `psql` command, or an error if it failed.
+fn psql_stdout(output: process::Output) -> Result<Vec<u8>, Error> {
+    if !output.status.success() {
+        return Err(Error::PsqlFailed(PsqlFailure::from_output(
+            output.status,
+            &output.stderr,
+        )));
     }
 
-    Ok(())
+    Ok(output.stdout)
+}
+
+/// Runs a query on `database` through `psql`, using the credentials from `url`.
+#[cfg(not(feature = "native-client"))]
+fn query_sql(
+    psql_binary: &path::Path,
+    url: &Url,
+    database: &str,
+    sql: &str,
+) -> Result<Vec<Row>, Error> {
+    // `psql` prints `NULL` and empty strings the same way by default.
+    let null_marker = generate_random_string();
+
+    let stdout = run_psql(
+        psql_command(psql_binary, url, database)
+            .args(["--no-psqlrc", "--quiet", "--csv", "--pset"])
+            .arg(format!("null={null_marker}"))
+            .arg("-c")
+            .arg(sql),
+    )?;
+
+    String::from_utf8(stdout)
+        .ok()
+        .and_then(|stdout| row::parse_csv(&stdout, &null_marker))
+        .ok_or(Error::InvalidPsqlOutput)
+}
+
+/// Runs a query on `database` through the native client, using the credentials from `url`.
+#[cfg(feature = "native-client")]
+fn query_sql(
+    _psql_binary: &path::Path,
+    url: &Url,
+    database: &str,
+    sql: &str,
+) -> Result<Vec<Row>, Error> {
+    native::simple_query(url, database, sql)
+}
+
+/// Returns SQL creating a user that is allowed to login.
+fn create_user_sql(username: &str, password: &str) -> String {
+    format!(
+        "CREATE ROLE {} LOGIN ENCRYPTED PASSWORD {};",
+        escape_ident(username),
+        escape_string(password)
+    )
+}
+
+/// Returns SQL creating a database with the given owner.
+fn create_database_sql(database: &str, owner: &str) -> String {
+    format!(
+        "CREATE DATABASE {} OWNER {};",
+        escape_ident(database),
+        escape_ident(owner)
+    )
+}
+
+/// Creates `extensions` in `database` using psql, unless they already exist.
+///
+/// Fails with [`Error::ExtensionUnavailable`] before creating any extension if one of them is not
+/// installed on the server.
+pub fn create_extensions(
+    binaries: &PgBinaries,
+    superuser_url: &Url,
+    database: &str,
+    extensions: &[String],
+) -> Result<(), Error> {
+    install_extensions(
+        &binaries.psql_or_default(),
+        superuser_url,
+        database,
+        extensions,
+    )
+}
+
+/// Creates `extensions` in `database` through `psql_binary`, see [`create_extensions`].
+fn install_extensions(
+    psql_binary: &path::Path,
+    superuser_url: &Url,
+    database: &str,
+    extensions: &[String],
+) -> Result<(), Error> {
+    if extensions.is_empty() {
+        return Ok(());
+    }
+
+    let available: Vec<String> = query_sql(
+        psql_binary,
+        superuser_url,
+        database,
+        "SELECT name FROM pg_available_extensions ORDER BY name",
+    )?
+    .iter()
+    .filter_map(|row| row.get(0).map(str::to_string))
+    .collect();
+    if let Some(missing) = extensions
+        .iter()
+        .find(|extension| !available.contains(extension))
+    {
+        return Err(Error::ExtensionUnavailable {
+            name: missing.clone(),
+            available,
+        });
+    }
+
+    let sql: String = extensions
+        .iter()
+        .map(|extension| {
+            format!(
+                "CREATE EXTENSION IF NOT EXISTS {} CASCADE;",
+                escape_ident(extension)
+            )
+        })
+        .collect();
+    execute_sql(psql_binary, superuser_url, database, &sql)
 }
 
 /// Creates a user and database with the given credentials using psql.
 pub fn create_user_and_database(
+    binaries: &PgBinaries,
     superuser_url: &Url,
     db_name: &str,
     db_user: &str,
@@ -79,48 +380,161 @@ pub fn create_user_and_database(
 ) -> Result<(), Error> {
     // Create user
     run_psql_command(
+        binaries,
+        superuser_url,
+        "postgres",
+        &create_user_sql(db_user, db_pw),
+    )?;
+
+    // Create database
+    run_psql_command(
+        binaries,
+        superuser_url,
+        "postgres",
+        &create_database_sql(db_name, db_user),
+    )?;
+
+    Ok(())
+}
+
+/// Creates a new fixture database with random credentials.
+fn create_fixture_db(binaries: &PgBinaries, superuser_url: &Url) -> Result<Url, Error> {
+    let url = random_credentials_url(superuser_url, "fixture");
+    let db_name = url.path().trim_start_matches('/');
+
+    create_user_and_database(
+        binaries,
+        superuser_url,
+        db_name,
+        url.username(),
+        url.password().unwrap_or_default(),
+    )?;
+
+    Ok(url)
+}
+
+/// Asynchronously creates a new fixture database with random credentials.
+#[cfg(feature = "async")]
+async fn create_fixture_db_async(binaries: &PgBinaries, superuser_url: &Url) -> Result<Url, Error> {
+    let psql_binary = binaries.psql_or_default();
+    let url = random_credentials_url(superuser_url, "fixture");
+    let db_name = url.path().trim_start_matches('/');
+
+    execute_sql_async(
+        &psql_binary,
+        superuser_url,
+        "postgres",
+        &create_user_sql(url.username(), url.password().unwrap_or_default()),
+    )
+    .await?;
+    execute_sql_async(
+        &psql_binary,
+        superuser_url,
+        "postgres",
+        &create_database_sql(db_name, url.username()),
+    )
+    .await?;
+
+    Ok(url)
+}
+
+/// Creates a new fixture database with random credentials as a copy of a template database.
+///
+/// Objects owned by the template's user are reassigned to the new fixture user.
+fn create_fixture_db_from_template(
+    binaries: &PgBinaries,
+    superuser_url: &Url,
+    template_url: &Url,
+) -> Result<Url, Error> {
+    let url = random_credentials_url(superuser_url, "fixture");
+    let db_name = url.path().trim_start_matches('/');
+    let template_name = template_url.path().trim_start_matches('/');
+
+    run_psql_command(
+        binaries,
+        superuser_url,
+        "postgres",
+        &create_user_sql(url.username(), url.password().unwrap_or_default()),
+    )?;
+    run_psql_command(
+        binaries,
         superuser_url,
         "postgres",
         &format!(
-            "CREATE ROLE {} LOGIN ENCRYPTED PASSWORD {};",
-            escape_ident(db_user),
-            escape_string(db_pw)
+            "CREATE DATABASE {} OWNER {} TEMPLATE {};",
+            escape_ident(db_name),
+            escape_ident(url.username()),
+            escape_ident(template_name)
+        ),
+    )?;
+    run_psql_command(
+        binaries,
+        superuser_url,
+        db_name,
+        &format!(
+            "REASSIGN OWNED BY {} TO {};",
+            escape_ident(template_url.username()),
+            escape_ident(url.username())
         ),
     )?;
 
-    // Create database
+    Ok(url)
+}
+
+/// Creates a new template database with random credentials.
+///
+/// The database itself is owned by the superuser, so that copies do not depend on the template's
+/// user. The template's user owns the `public` schema and may create objects in the database.
+fn create_template_db(binaries: &PgBinaries, superuser_url: &Url) -> Result<Url, Error> {
+    let url = random_credentials_url(superuser_url, "template");
+    let db_name = url.path().trim_start_matches('/');
+
+    create_user_and_database(
+        binaries,
+        superuser_url,
+        db_name,
+        url.username(),
+        url.password().unwrap_or_default(),
+    )?;
     run_psql_command(
+        binaries,
         superuser_url,
         "postgres",
         &format!(
-            "CREATE DATABASE {} OWNER {};",
+            "ALTER DATABASE {} OWNER TO {}; GRANT ALL ON DATABASE {} TO {};",
+            escape_ident(db_name),
+            escape_ident(superuser_url.username()),
             escape_ident(db_name),
-            escape_ident(db_user)
+            escape_ident(url.username())
+        ),
+    )?;
+    run_psql_command(
+        binaries,
+        superuser_url,
+        db_name,
+        &format!(
+            "ALTER SCHEMA public OWNER TO {};",
+            escape_ident(url.username())
         ),
     )?;
 
-    Ok(())
+    Ok(url)
 }
 
-/// Creates a new fixture database with random credentials.
-fn create_fixture_db(superuser_url: &Url) -> Result<Url, Error> {
+/// Builds a URL with random credentials and database name, using `prefix` for all names.
+fn random_credentials_url(superuser_url: &Url, prefix: &str) -> Url {
     // Generate unique credentials with random IDs
     let random_id = generate_random_string();
-    let db_name = format!("fixture_db_{}", random_id);
-    let db_user = format!("fixture_user_{}", random_id);
-    let db_pw = format!("fixture_pass_{}", random_id);
+    let db_name = format!("{}_db_{}", prefix, random_id);
+    let db_user = format!("{}_user_{}", prefix, random_id);
+    let db_pw = format!("{}_pass_{}", prefix, random_id);
 
-    // Create user and database
-    create_user_and_database(superuser_url, &db_name, &db_user, &db_pw)?;
-
-    // Build the URL for the new database
     let mut url = superuser_url.clone();
     url.set_username(&db_user).expect("Failed to set username");
     url.set_password(Some(&db_pw))
         .expect("Failed to set password");
     url.set_path(&db_name);
-
-    Ok(url)
+    url
 }
 
 /// Finds an unused port by binding to port 0 and letting the OS assign one.
@@ -143,10 +557,15 @@ pub struct Postgres {
     /// URL for the instance with superuser credentials.
     superuser_url: Url,
     /// PostgreSQL process and its temporary directory.
-    #[allow(dead_code)] // Only used for its `Drop` implementation.
     process: PostgresProcess,
+    /// Configuration to relaunch the process with.
+    launcher: Launcher,
     /// Path to the `psql` binary.
     psql_binary: path::PathBuf,
+    /// Worker dropping fixture databases in the background, started on first use.
+    cleanup: Mutex<Option<db_instance::CleanupWorker>>,
+    /// Superuser URL of the primary this server replicates, if it is a replica.
+    primary_url: Option<Url>,
 }
 
 /// Resources owned by a PostgreSQL process.
@@ -158,6 +577,39 @@ struct PostgresProcess {
     tmp_dir: tempfile::TempDir,
 }
 
+impl Drop for Postgres {
+    fn drop(&mut self) {
+        // Databases still queued for removal disappear with the server anyway.
+        let cleanup = match self.cleanup.get_mut() {
+            Ok(cleanup) => cleanup.take(),
+            Err(poisoned) => poisoned.into_inner().take(),
+        };
+        if let Some(cleanup) = cleanup {
+            cleanup.shutdown();
+        }
+
+        if !(self.launcher.builder.keep_on_panic && thread::panicking()) {
+            return;
+        }
+
+        let pid = self.process.instance.take().map(|child| child.id());
+        self.process.tmp_dir.disable_cleanup(true);
+
+        eprintln!("pgdb: keeping PostgreSQL server after panic");
+        if let Some(pid) = pid {
+            eprintln!("  pid:      {pid}");
+        }
+        eprintln!("  data dir: {}", self.launcher.data_dir.display());
+        if !self.launcher.builder.tcp {
+            eprintln!("  socket:   {}", self.process.tmp_dir.path().display());
+        }
+        if let Some(log_path) = self.log_path() {
+            eprintln!("  log:      {}", log_path.display());
+        }
+        eprintln!("  url:      {}", self.superuser_url);
+    }
+}
+
 impl Drop for PostgresProcess {
     fn drop(&mut self) {
         if self.instance.shutdown().is_err() {
@@ -181,10 +633,14 @@ pub struct PostgresClient<'a> {
 /// Builder for a postgres instance.
 ///
 /// Usually constructed via [`Postgres::build`].
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct PostgresBuilder {
     /// Data directory.
     data_dir: Option<path::PathBuf>,
+    /// Whether to reuse an existing cluster in the data directory.
+    persistent: bool,
+    /// Directory caching initialized clusters, if enabled.
+    initdb_cache: Option<path::PathBuf>,
     /// TCP listening port.
     ///
     /// If not set, [`find_unused_port`] will be used to determine the port.
@@ -193,22 +649,47 @@ pub struct PostgresBuilder {
     host: String,
     /// Whether to use fast settings for disposable servers.
     fast: bool,
+    /// Whether to write server logs to a file instead of inheriting stdout and stderr.
+    log_to_file: bool,
+    /// Whether to leave the server and its files behind when dropped during a panic.
+    keep_on_panic: bool,
     /// Whether to connect over TCP.
     tcp: bool,
+    /// Whether to enable TLS with generated certificates.
+    #[cfg(feature = "tls")]
+    tls: bool,
+    /// Method clients authenticate with.
+    auth: AuthMethod,
+    /// Custom `pg_hba.conf` lines.
+    pg_hba_lines: Vec<String>,
     /// Name of the superuser.
     superuser: String,
     /// Password for the superuser.
     superuser_pw: String,
-    /// Path to `postgres` binary.
-    postgres_binary: Option<path::PathBuf>,
-    /// Path to `initdb` binary.
-    initdb_binary: Option<path::PathBuf>,
-    /// Path to `pg_isready` binary.
-    pg_isready_binary: Option<path::PathBuf>,
-    /// Path to `psql` binary.
-    psql_binary: Option<path::PathBuf>,
+    /// Paths to PostgreSQL binaries.
+    binaries: PgBinaries,
+    /// Required PostgreSQL version, if any.
+    version: Option<String>,
     /// PostgreSQL server configuration overrides.
     postgres_options: Vec<(String, String)>,
+    /// Extensions to create in every new database.
+    extensions: Vec<String>,
+    /// Locale of new clusters, `C` if not set.
+    locale: Option<String>,
+    /// Collation order of new clusters, overriding `locale`.
+    lc_collate: Option<String>,
+    /// Character classification of new clusters, overriding `locale`.
+    lc_ctype: Option<String>,
+    /// Default encoding of new clusters.
+    encoding: String,
+    /// ICU locale, selecting ICU as the collation provider if set.
+    icu_locale: Option<String>,
+    /// Whether to enable data checksums.
+    data_checksums: bool,
+    /// Additional `initdb` arguments.
+    extra_initdb_args: Vec<ffi::OsString>,
+    /// Configuration files included by `postgresql.conf`.
+    config_files: Vec<path::PathBuf>,
     /// How long to wait between startup probe attempts.
     probe_delay: Duration,
     /// Time until giving up waiting for startup.
@@ -225,17 +706,32 @@ impl Postgres {
     pub fn build() -> PostgresBuilder {
         PostgresBuilder {
             data_dir: None,
+            persistent: false,
+            initdb_cache: None,
             port: None,
             host: "127.0.0.1".to_string(),
             fast: false,
+            log_to_file: false,
+            keep_on_panic: false,
             tcp: false,
+            #[cfg(feature = "tls")]
+            tls: false,
+            auth: AuthMethod::default(),
+            pg_hba_lines: Vec::new(),
             superuser: "postgres".to_string(),
             superuser_pw: generate_random_string(),
-            postgres_binary: None,
-            initdb_binary: None,
-            pg_isready_binary: None,
-            psql_binary: None,
+            binaries: PgBinaries::new(),
+            version: None,
             postgres_options: Vec::new(),
+            extensions: Vec::new(),
+            locale: None,
+            lc_collate: None,
+            lc_ctype: None,
+            encoding: "UTF8".to_string(),
+            icu_locale: None,
+            data_checksums: false,
+            extra_initdb_args: Vec::new(),
+            config_files: Vec::new(),
             probe_delay: Duration::from_millis(100),
             startup_timeout: Duration::from_secs(10),
             shutdown_timeout: Duration::from_secs(5),
@@ -253,6 +749,9 @@ impl Postgres {
     }
 
     /// Returns a postgres client that uses the given credentials.
+    ///
+    /// With `AuthMethod::Cert` (`tls` feature), a client certificate for `username` is generated on
+    /// first use and added to the client's URLs.
     #[inline]
     pub fn as_user(&self, username: &str, password: &str) -> PostgresClient<'_> {
         let mut client_url = self.superuser_url.clone();
@@ -262,6 +761,16 @@ impl Postgres {
         client_url
             .set_password(Some(password))
             .expect("Failed to set password");
+        #[cfg(feature = "tls")]
+        if self.launcher.builder.auth == AuthMethod::Cert {
+            let (cert, key) = tls::TlsFiles::new(self.process.tmp_dir.path())
+                .client_cert(username)
+                .expect("Failed to generate client certificate");
+            client_url
+                .query_pairs_mut()
+                .append_pair("sslcert", &cert.to_string_lossy())
+                .append_pair("sslkey", &key.to_string_lossy());
+        }
         PostgresClient {
             instance: self,
             client_url,
@@ -272,147 +781,555 @@ impl Postgres {
     pub fn superuser_url(&self) -> &Url {
         &self.superuser_url
     }
-}
 
-impl<'a> PostgresClient<'a> {
-    /// Runs a `psql` command against the database.
+    /// Starts a hot standby streaming changes from this server.
     ///
-    /// Creates a command that runs `psql -h (host) -p (port) -U (username) -d (database)` with
-    /// `PGPASSWORD` set.
-    pub fn psql(&self, database: &str) -> process::Command {
-        let mut cmd = process::Command::new(&self.instance.psql_binary);
-
-        let username = self.client_url.username();
-        let password = self.client_url.password().unwrap_or_default();
-
-        let host = connection_host(&self.client_url).expect("Client URL must have a host");
-        let port = connection_port(&self.client_url).expect("Client URL must have a port");
-
-        cmd.arg("-h")
-            .arg(host.as_ref())
-            .arg("-p")
-            .arg(port.to_string())
-            .arg("-U")
-            .arg(username)
-            .arg("-d")
-            .arg(database)
-            .env("PGPASSWORD", password);
-
-        cmd
-    }
-
-    /// Runs the given SQL commands from an input file via `psql`.
-    pub fn load_sql<P: AsRef<path::Path>>(&self, database: &str, filename: P) -> Result<(), Error> {
-        let status = self
-            .psql(database)
-            .arg("-f")
-            .arg(filename.as_ref())
+    /// The standby is initialized from a base backup taken through `pg_basebackup` and runs with
+    /// the same settings as this server, in its own temporary directory. Use
+    /// [`Postgres::wait_for_replay`] to wait for changes to arrive and [`Postgres::promote`] to turn
+    /// it into a primary.
+    ///
+    /// Replicas can be started from other replicas as well, unless the original primary runs with
+    /// [`PostgresBuilder::fast`] settings, which standbys refuse to take base backups of.
+    pub fn start_replica(&self) -> Result<Postgres, Error> {
+        let mut builder = self.launcher.builder.clone();
+        builder.data_dir = None;
+        builder.persistent = false;
+        builder.initdb_cache = None;
+        builder.port = None;
+        // Extensions are replicated and not created again, as the standby is never passed through
+        // `install_extensions`. Their libraries still need to be preloaded.
+        builder.superuser_pw =
+            percent_decode_str(self.superuser_url.password().unwrap_or_default())
+                .decode_utf8_lossy()
+                .into_owned();
+        // Replication requires the same major version, so stick to this server's installation.
+        if let Some(bin_dir) = self.launcher.postgres_binary.parent() {
+            builder.binaries.bin_dir(bin_dir);
+        }
+        builder
+            .binaries
+            .postgres(self.launcher.postgres_binary.clone());
+        let pg_basebackup_binary = builder.binaries.find_pg_basebackup()?;
+
+        let mut startup = builder.prepare()?;
+        let status = client_command(&pg_basebackup_binary, &self.superuser_url)
+            .arg("--pgdata")
+            .arg(&startup.data_dir)
+            .args([
+                "--write-recovery-conf",
+                "--wal-method=stream",
+                "--checkpoint=fast",
+                "--no-sync",
+            ])
+            .stdout(process::Stdio::null())
             .status()
-            .map_err(Error::RunPsql)?;
-
+            .map_err(Error::RunPgBasebackup)?;
         if !status.success() {
-            return Err(Error::PsqlFailed(status));
+            return Err(Error::PgBasebackupFailed(status));
         }
+        startup.initialized = true;
 
-        Ok(())
+        let launched = builder.launch(startup)?;
+        launched
+            .launcher
+            .wait_until_ready(launched.process.tmp_dir.path())?;
+
+        let mut replica = builder.finish(launched);
+        replica.primary_url = Some(self.superuser_url.clone());
+        Ok(replica)
     }
 
-    /// Runs the given SQL command through `psql`.
-    pub fn run_sql(&self, database: &str, sql: &str) -> Result<(), Error> {
-        let status = self
-            .psql(database)
-            .arg("-c")
-            .arg(sql)
-            .status()
-            .map_err(Error::RunPsql)?;
+    /// Waits until this replica has replayed all changes committed on its primary so far.
+    ///
+    /// Fails with [`Error::NotAReplica`] unless the server was started through
+    /// [`Postgres::start_replica`] and not promoted yet, and with [`Error::ReplayTimeout`] if the
+    /// changes are not replayed within `timeout`.
+    pub fn wait_for_replay(&self, timeout: Duration) -> Result<(), Error> {
+        let primary_url = self.primary_url.as_ref().ok_or(Error::NotAReplica)?;
+        // The insert position includes commits not flushed yet, e.g. with `synchronous_commit=off`.
+        // A primary that is a replica itself has no insert position, but passes on what it replayed.
+        let lsn = query_sql(
+            &self.psql_binary,
+            primary_url,
+            "postgres",
+            "SELECT CASE WHEN pg_is_in_recovery() THEN pg_last_wal_replay_lsn() \
+             ELSE pg_current_wal_insert_lsn() END",
+        )?
+        .first()
+        .and_then(|row| row.get(0))
+        .map(escape_string)
+        .ok_or(Error::InvalidPsqlOutput)?;
+
+        let sql = format!("SELECT pg_last_wal_replay_lsn() >= {lsn}::pg_lsn");
+        let started = Instant::now();
+        loop {
+            if self
+                .as_superuser()
+                .query_scalar("postgres", &sql)?
+                .as_deref()
+                == Some("t")
+            {
+                return Ok(());
+            }
+            if started.elapsed() >= timeout {
+                return Err(Error::ReplayTimeout);
+            }
+            thread::sleep(self.launcher.builder.probe_delay);
+        }
+    }
 
-        if !status.success() {
-            return Err(Error::PsqlFailed(status));
+    /// Promotes this replica to a primary, waiting until it accepts writes.
+    ///
+    /// Fails with [`Error::NotAReplica`] unless the server was started through
+    /// [`Postgres::start_replica`] and not promoted yet.
+    pub fn promote(&mut self) -> Result<(), Error> {
+        if self.primary_url.is_none() {
+            return Err(Error::NotAReplica);
         }
 
-        Ok(())
+        match self
+            .as_superuser()
+            .query_scalar("postgres", "SELECT pg_promote()")?
+            .as_deref()
+        {
+            Some("t") => {
+                self.primary_url = None;
+                Ok(())
+            }
+            _ => Err(Error::PromoteFailed),
+        }
     }
 
-    /// Creates a new database with the given owner.
-    ///
-    /// This typically requires superuser credentials, see [`Postgres::as_superuser`].
+    /// Returns the version of the running server.
     #[inline]
-    pub fn create_database(&self, database: &str, owner: &str) -> Result<(), Error> {
-        self.run_sql(
-            "postgres",
-            &format!(
-                "CREATE DATABASE {} OWNER {};",
-                escape_ident(database),
-                escape_ident(owner)
-            ),
-        )
+    pub fn server_version(&self) -> PgVersion {
+        self.launcher.version
     }
 
-    /// Creates a new user on the system that is allowed to login.
+    /// Returns the certificate of the CA that signed the server certificate.
     ///
-    /// This typically requires superuser credentials, see [`Postgres::as_superuser`].
-    #[inline]
-    pub fn create_user(&self, username: &str, password: &str) -> Result<(), Error> {
-        self.run_sql(
-            "postgres",
-            &format!(
-                "CREATE ROLE {} LOGIN ENCRYPTED PASSWORD {};",
-                escape_ident(username),
-                escape_string(password)
-            ),
-        )
+    /// Returns `None` unless TLS was enabled with [`PostgresBuilder::tls`].
+    #[cfg(feature = "tls")]
+    pub fn ca_cert_path(&self) -> Option<path::PathBuf> {
+        self.launcher
+            .builder
+            .tls
+            .then(|| tls::TlsFiles::new(self.process.tmp_dir.path()).ca_cert)
     }
 
-    /// Returns the `Postgres` instance associated with this client.
-    #[inline]
-    pub fn instance(&self) -> &Postgres {
-        self.instance
+    /// Returns the process ID of the `postgres` server process.
+    ///
+    /// Returns `None` if the server could not be restarted after a snapshot or restore.
+    pub fn pid(&self) -> Option<u32> {
+        self.process.instance.id()
     }
 
-    /// Returns a libpq-style connection URL.
-    pub fn url(&self, database: &str) -> Url {
-        let mut url = self.client_url.clone();
-        url.set_path(database);
-        url
+    /// Returns the location of the server log, if enabled with [`PostgresBuilder::log_to_file`].
+    pub fn log_path(&self) -> Option<&path::Path> {
+        self.launcher.log_path.as_deref()
     }
 
-    /// Returns the client URL for this client.
-    pub fn client_url(&self) -> &Url {
-        &self.client_url
+    /// Returns up to `lines` of the most recent server log lines, e.g. to attach to test failures.
+    ///
+    /// Returns `None` if the log is not written to a file or cannot be read.
+    pub fn recent_logs(&self, lines: usize) -> Option<String> {
+        let log = fs::read_to_string(self.log_path()?).ok()?;
+        let skip = log.lines().count().saturating_sub(lines);
+        Some(log.lines().skip(skip).collect::<Vec<_>>().join("\n"))
     }
-}
 
-impl PostgresBuilder {
-    /// Enables faster settings for a disposable PostgreSQL server.
+    /// Saves a copy of the entire cluster under the given name.
     ///
-    /// This disables runtime durability and uses immediate shutdown. It must not be used when the
-    /// database needs to survive an operating system crash or server shutdown.
-    #[inline]
-    pub fn fast(&mut self) -> &mut Self {
-        self.fast = true;
-        self
+    /// The server is stopped while its data directory is copied into the instance's temporary
+    /// directory and restarted afterwards. An existing snapshot with the same name is replaced,
+    /// unless copying fails. Use [`Postgres::restore`] to reset the cluster to the snapshot.
+    pub fn snapshot(&mut self, name: &str) -> Result<(), Error> {
+        let snapshot_dir = self.snapshot_dir(name)?;
+
+        self.while_stopped(|data_dir| replace_with_copy(data_dir, &snapshot_dir))
     }
 
-    /// Sets the postgres data directory.
+    /// Resets the entire cluster to a snapshot created by [`Postgres::snapshot`].
     ///
-    /// If not set, a temporary directory will be used.
-    #[inline]
-    pub fn data_dir<T: Into<path::PathBuf>>(&mut self, data_dir: T) -> &mut Self {
-        self.data_dir = Some(data_dir.into());
-        self
-    }
+    /// Includes all roles and databases. Clients must reconnect, as the server is restarted. If
+    /// copying fails, the cluster is left unchanged.
+    pub fn restore(&mut self, name: &str) -> Result<(), Error> {
+        let snapshot_dir = self.snapshot_dir(name)?;
+        if !snapshot_dir.exists() {
+            return Err(Error::UnknownSnapshot(name.to_string()));
+        }
 
-    /// Sets the location of the `initdb` binary.
-    #[inline]
-    pub fn initdb_binary<T: Into<path::PathBuf>>(&mut self, initdb_binary: T) -> &mut Self {
-        self.initdb_binary = Some(initdb_binary.into());
-        self
+        self.while_stopped(|data_dir| replace_with_copy(&snapshot_dir, data_dir))
     }
 
-    /// Sets the location of the `pg_isready` binary.
+    /// Returns the directory holding the snapshot with the given name.
+    fn snapshot_dir(&self, name: &str) -> Result<path::PathBuf, Error> {
+        if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+            return Err(Error::InvalidSnapshotName(name.to_string()));
+        }
+
+        Ok(self.process.tmp_dir.path().join("snapshots").join(name))
+    }
+
+    /// Stops the server, runs `action` on its data directory and restarts it.
+    ///
+    /// The server is restarted even if `action` fails, unless the data directory was lost.
+    fn while_stopped<F>(&mut self, action: F) -> Result<(), Error>
+    where
+        F: FnOnce(&path::Path) -> io::Result<()>,
+    {
+        // Fast mode shuts down immediately and does not flush commits on its own.
+        self.as_superuser().run_sql("postgres", "CHECKPOINT;")?;
+        self.process
+            .instance
+            .shutdown()
+            .map_err(Error::StopPostgres)?;
+
+        let result = action(&self.launcher.data_dir).map_err(Error::Snapshot);
+        if result.is_err() && !self.launcher.data_dir.join("PG_VERSION").exists() {
+            return result;
+        }
+
+        let socket_dir = self.process.tmp_dir.path();
+        self.process.instance = self.launcher.spawn(socket_dir)?;
+        self.launcher.wait_until_ready(socket_dir)?;
+
+        result
+    }
+
+    /// Drops the database and role referenced by `url` on a background thread.
+    fn drop_database_and_role_later(&self, url: Url) {
+        let mut cleanup = match self.cleanup.lock() {
+            Ok(cleanup) => cleanup,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        cleanup
+            .get_or_insert_with(|| {
+                db_instance::CleanupWorker::spawn(
+                    self.psql_binary.clone(),
+                    self.superuser_url.clone(),
+                )
+            })
+            .submit(url);
+    }
+
+    /// Leaves the server running after this instance is dropped.
+    ///
+    /// The returned state must be used to stop the server and clean up its temporary directory.
+    fn detach(mut self) -> shared::ServerState {
+        let _ = self.process.instance.take();
+        self.process.tmp_dir.disable_cleanup(true);
+
+        shared::ServerState {
+            superuser_url: self.superuser_url.clone(),
+            data_dir: self.launcher.data_dir.clone(),
+            tmp_dir: self.process.tmp_dir.path().to_owned(),
+            shutdown_mode: if self.launcher.builder.fast {
+                "immediate"
+            } else {
+                "fast"
+            },
+        }
+    }
+}
+
+impl<'a> PostgresClient<'a> {
+    /// Runs a `psql` command against the database.
+    ///
+    /// Creates a command that runs `psql -h (host) -p (port) -U (username) -d (database)` with
+    /// `PGPASSWORD` set.
+    pub fn psql(&self, database: &str) -> process::Command {
+        psql_command(&self.instance.psql_binary, &self.client_url, database)
+    }
+
+    /// Runs the given SQL commands from an input file via `psql`.
+    ///
+    /// Stops at the first failing statement, whose location is reported in the returned error.
+    pub fn load_sql<P: AsRef<path::Path>>(&self, database: &str, filename: P) -> Result<(), Error> {
+        load_sql_file(
+            &self.instance.psql_binary,
+            &self.client_url,
+            database,
+            filename.as_ref(),
+        )
+    }
+
+    /// Runs the given SQL command through `psql`.
+    ///
+    /// With the `native-client` feature enabled, the SQL is sent directly to the server instead.
+    pub fn run_sql(&self, database: &str, sql: &str) -> Result<(), Error> {
+        execute_sql(&self.instance.psql_binary, &self.client_url, database, sql)
+    }
+
+    /// Runs the given SQL command through `psql` without blocking the async runtime.
+    ///
+    /// See [`PostgresClient::run_sql`] for details.
+    #[cfg(feature = "async")]
+    pub async fn run_sql_async(&self, database: &str, sql: &str) -> Result<(), Error> {
+        execute_sql_async(&self.instance.psql_binary, &self.client_url, database, sql).await
+    }
+
+    /// Runs the given query and returns all resulting rows.
+    ///
+    /// `sql` should contain a single statement. All values are returned in PostgreSQL's text
+    /// format, with `NULL` values represented as `None`.
+    pub fn query(&self, database: &str, sql: &str) -> Result<Vec<Row>, Error> {
+        query_sql(&self.instance.psql_binary, &self.client_url, database, sql)
+    }
+
+    /// Runs the given query and returns the first column of the first row.
+    ///
+    /// Returns `None` if the query returned no rows or the value is `NULL`.
+    pub fn query_scalar(&self, database: &str, sql: &str) -> Result<Option<String>, Error> {
+        Ok(self
+            .query(database, sql)?
+            .first()
+            .and_then(|row| row.get(0))
+            .map(str::to_string))
+    }
+
+    /// Creates a new database with the given owner.
+    ///
+    /// This typically requires superuser credentials, see [`Postgres::as_superuser`].
+    #[inline]
+    pub fn create_database(&self, database: &str, owner: &str) -> Result<(), Error> {
+        self.run_sql("postgres", &create_database_sql(database, owner))
+    }
+
+    /// Creates a new user on the system that is allowed to login.
+    ///
+    /// This typically requires superuser credentials, see [`Postgres::as_superuser`].
+    #[inline]
+    pub fn create_user(&self, username: &str, password: &str) -> Result<(), Error> {
+        self.run_sql("postgres", &create_user_sql(username, password))
+    }
+
+    /// Returns whether a user (or any other role) with the given name exists.
+    pub fn user_exists(&self, username: &str) -> Result<bool, Error> {
+        Ok(self
+            .query_scalar(
+                "postgres",
+                &format!(
+                    "SELECT 1 FROM pg_roles WHERE rolname = {};",
+                    escape_string(username)
+                ),
+            )?
+            .is_some())
+    }
+
+    /// Returns whether a database with the given name exists.
+    pub fn database_exists(&self, database: &str) -> Result<bool, Error> {
+        Ok(self
+            .query_scalar(
+                "postgres",
+                &format!(
+                    "SELECT 1 FROM pg_database WHERE datname = {};",
+                    escape_string(database)
+                ),
+            )?
+            .is_some())
+    }
+
+    /// Returns the `Postgres` instance associated with this client.
+    #[inline]
+    pub fn instance(&self) -> &Postgres {
+        self.instance
+    }
+
+    /// Returns a libpq-style connection URL.
+    pub fn url(&self, database: &str) -> Url {
+        let mut url = self.client_url.clone();
+        url.set_path(database);
+        url
+    }
+
+    /// Returns the client URL for this client.
+    pub fn client_url(&self) -> &Url {
+        &self.client_url
+    }
+}
+
+impl PostgresBuilder {
+    /// Enables faster settings for a disposable PostgreSQL server.
+    ///
+    /// This disables runtime durability and uses immediate shutdown. It must not be used when the
+    /// database needs to survive an operating system crash or server shutdown.
+    #[inline]
+    pub fn fast(&mut self) -> &mut Self {
+        self.fast = true;
+        self
+    }
+
+    /// Writes the server's output to a log file instead of inheriting stdout and stderr.
+    ///
+    /// The log file is kept in the temporary directory, see [`Postgres::log_path`] and
+    /// [`Postgres::recent_logs`].
+    #[inline]
+    pub fn log_to_file(&mut self) -> &mut Self {
+        self.log_to_file = true;
+        self
+    }
+
+    /// Leaves the server running and keeps its files if the instance is dropped during a panic.
+    ///
+    /// Intended for inspecting the cluster after a failing test. The locations of the data
+    /// directory, socket and log are printed to stderr.
+    #[inline]
+    pub fn keep_on_panic(&mut self) -> &mut Self {
+        self.keep_on_panic = true;
+        self
+    }
+
+    /// Sets the postgres data directory.
+    ///
+    /// If not set, a temporary directory will be used.
+    #[inline]
+    pub fn data_dir<T: Into<path::PathBuf>>(&mut self, data_dir: T) -> &mut Self {
+        self.data_dir = Some(data_dir.into());
+        self
+    }
+
+    /// Reuses the cluster in the data directory across instances.
+    ///
+    /// Requires a [`PostgresBuilder::data_dir`]. If the data directory does not contain a cluster
+    /// yet, it is initialized and the superuser password is stored alongside the data. Otherwise
+    /// `initdb` is skipped and the stored superuser password is used instead of the configured
+    /// one. Clusters not initialized by `pgdb` have no stored password, so the configured
+    /// [`PostgresBuilder::superuser_pw`] must match theirs. The data directory is left in place when
+    /// the instance is dropped.
+    #[inline]
+    pub fn persistent(&mut self) -> &mut Self {
+        self.persistent = true;
+        self
+    }
+
+    /// Copies new clusters from a cache instead of running `initdb` every time.
+    ///
+    /// Uses `$XDG_CACHE_HOME/pgdb`, falling back to `$HOME/.cache/pgdb`. Does nothing if neither
+    /// environment variable is set. See [`PostgresBuilder::initdb_cache_dir`] for details.
+    #[inline]
+    pub fn initdb_cache(&mut self) -> &mut Self {
+        self.initdb_cache = initdb_cache::default_dir();
+        self
+    }
+
+    /// Copies new clusters from a cache in `cache_dir` instead of running `initdb` every time.
+    ///
+    /// The first server started with a given `initdb` version, superuser and set of `initdb`
+    /// arguments initializes a cluster in the cache, later ones copy it. The cache can safely be
+    /// shared by concurrent processes. It is not used for [`PostgresBuilder::persistent`] clusters.
+    /// Entries are never evicted, so the cache grows by one cluster per combination.
+    ///
+    /// Failures to read or populate the cache are logged to stderr, the cluster is then initialized
+    /// by `initdb` as usual.
+    #[inline]
+    pub fn initdb_cache_dir<T: Into<path::PathBuf>>(&mut self, cache_dir: T) -> &mut Self {
+        self.initdb_cache = Some(cache_dir.into());
+        self
+    }
+
+    /// Sets the locations of all PostgreSQL binaries, replacing previously set ones.
+    #[inline]
+    pub fn binaries(&mut self, binaries: PgBinaries) -> &mut Self {
+        self.binaries = binaries;
+        self
+    }
+
+    /// Sets the directory containing the PostgreSQL binaries, e.g. `/usr/lib/postgresql/16/bin`.
+    ///
+    /// Binaries set individually take precedence.
+    #[inline]
+    pub fn pg_bin_dir<T: Into<path::PathBuf>>(&mut self, bin_dir: T) -> &mut Self {
+        self.binaries.bin_dir(bin_dir);
+        self
+    }
+
+    /// Requires a PostgreSQL version, e.g. `"16"`, `">=15"` or `">=14, <17"`.
+    ///
+    /// Unless a binary directory or `postgres` binary is set, the newest installation satisfying the
+    /// requirement is selected through [`discovery`]. Otherwise, starting fails if the configured
+    /// `postgres` binary does not satisfy it. See [`discovery::VersionReq`] for the syntax.
+    #[inline]
+    pub fn version<T: Into<String>>(&mut self, requirement: T) -> &mut Self {
+        self.version = Some(requirement.into());
+        self
+    }
+
+    /// Sets the locale of new clusters, e.g. `en_US.UTF-8`. Defaults to `C`.
+    #[inline]
+    pub fn locale<T: Into<String>>(&mut self, locale: T) -> &mut Self {
+        self.locale = Some(locale.into());
+        self
+    }
+
+    /// Sets the collation order of new clusters, overriding [`PostgresBuilder::locale`].
+    #[inline]
+    pub fn lc_collate<T: Into<String>>(&mut self, lc_collate: T) -> &mut Self {
+        self.lc_collate = Some(lc_collate.into());
+        self
+    }
+
+    /// Sets the character classification of new clusters, overriding [`PostgresBuilder::locale`].
+    #[inline]
+    pub fn lc_ctype<T: Into<String>>(&mut self, lc_ctype: T) -> &mut Self {
+        self.lc_ctype = Some(lc_ctype.into());
+        self
+    }
+
+    /// Sets the default encoding of new clusters. Defaults to `UTF8`.
+    #[inline]
+    pub fn encoding<T: Into<String>>(&mut self, encoding: T) -> &mut Self {
+        self.encoding = encoding.into();
+        self
+    }
+
+    /// Uses ICU with the given locale, e.g. `en-US`, as the default collation provider.
+    ///
+    /// Requires PostgreSQL 15 or later built with ICU support.
+    #[inline]
+    pub fn icu_locale<T: Into<String>>(&mut self, icu_locale: T) -> &mut Self {
+        self.icu_locale = Some(icu_locale.into());
+        self
+    }
+
+    /// Enables data checksums in new clusters.
+    #[inline]
+    pub fn data_checksums(&mut self) -> &mut Self {
+        self.data_checksums = true;
+        self
+    }
+
+    /// Adds an argument to pass to `initdb`, e.g. `--wal-segsize=64`.
+    ///
+    /// Arguments are passed after the ones generated by `pgdb`, which they may override.
+    #[inline]
+    pub fn initdb_arg<T: Into<ffi::OsString>>(&mut self, arg: T) -> &mut Self {
+        self.extra_initdb_args.push(arg.into());
+        self
+    }
+
+    /// Includes a configuration file from `postgresql.conf`, e.g. to share settings with production.
+    ///
+    /// Files are included in order. Settings from [`PostgresBuilder::postgres_option`] take
+    /// precedence.
+    #[inline]
+    pub fn config_file<T: Into<path::PathBuf>>(&mut self, path: T) -> &mut Self {
+        self.config_files.push(path.into());
+        self
+    }
+
+    /// Sets the location of the `initdb` binary.
+    #[inline]
+    pub fn initdb_binary<T: Into<path::PathBuf>>(&mut self, initdb_binary: T) -> &mut Self {
+        self.binaries.initdb(initdb_binary);
+        self
+    }
+
+    /// Sets the location of the `pg_isready` binary.
     #[inline]
     pub fn pg_isready_binary<T: Into<path::PathBuf>>(&mut self, pg_isready_binary: T) -> &mut Self {
-        self.pg_isready_binary = Some(pg_isready_binary.into());
+        self.binaries.pg_isready(pg_isready_binary);
         self
     }
 
@@ -443,10 +1360,50 @@ impl PostgresBuilder {
         self
     }
 
+    /// Enables TLS using a throwaway CA and server certificate, implies [`PostgresBuilder::tcp`].
+    ///
+    /// The certificates are generated into the temporary directory. Connection URLs require
+    /// verified TLS through `sslmode=verify-full` and point `sslrootcert` at
+    /// [`Postgres::ca_cert_path`].
+    #[cfg(feature = "tls")]
+    #[inline]
+    pub fn tls(&mut self) -> &mut Self {
+        self.tls = true;
+        self.tcp = true;
+        self
+    }
+
+    /// Sets the method clients authenticate with, defaults to [`AuthMethod::ScramSha256`].
+    ///
+    /// The superuser always authenticates with a password unless [`AuthMethod::Trust`] is used,
+    /// since it is used to manage the server.
+    ///
+    /// [`PostgresBuilder::persistent`] clusters created by earlier versions of `pgdb` store MD5
+    /// password hashes, which SCRAM-SHA-256 rejects. Use [`AuthMethod::Md5`] for those.
+    #[inline]
+    pub fn auth(&mut self, auth: AuthMethod) -> &mut Self {
+        #[cfg(feature = "tls")]
+        if auth == AuthMethod::Cert {
+            self.tls();
+        }
+        self.auth = auth;
+        self
+    }
+
+    /// Adds a line to the server's `pg_hba.conf`, e.g. `host all readonly all reject`.
+    ///
+    /// Lines are added in order after the superuser's rules and before the rules generated for
+    /// [`PostgresBuilder::auth`], so they take precedence over the latter.
+    #[inline]
+    pub fn pg_hba_line<T: Into<String>>(&mut self, line: T) -> &mut Self {
+        self.pg_hba_lines.push(line.into());
+        self
+    }
+
     /// Sets the location of the `postgres` binary.
     #[inline]
     pub fn postgres_binary<T: Into<path::PathBuf>>(&mut self, postgres_binary: T) -> &mut Self {
-        self.postgres_binary = Some(postgres_binary.into());
+        self.binaries.postgres(postgres_binary);
         self
     }
 
@@ -463,6 +1420,17 @@ impl PostgresBuilder {
         self
     }
 
+    /// Adds an extension to create in every new database.
+    ///
+    /// Once started, the extension is created in `template1`, which every database created
+    /// afterwards is copied from. Starting fails with [`Error::ExtensionUnavailable`] if it is not
+    /// installed. Extensions like `pg_stat_statements` are added to `shared_preload_libraries`.
+    #[inline]
+    pub fn extension<T: Into<String>>(&mut self, name: T) -> &mut Self {
+        self.extensions.push(name.into());
+        self
+    }
+
     /// Sets the startup probe delay.
     ///
     /// Between two startup probes, waits this long.
@@ -475,7 +1443,7 @@ impl PostgresBuilder {
     /// Sets the location of the `psql` binary.
     #[inline]
     pub fn psql_binary<T: Into<path::PathBuf>>(&mut self, psql_binary: T) -> &mut Self {
-        self.psql_binary = Some(psql_binary.into());
+        self.binaries.psql(psql_binary);
         self
     }
 
@@ -512,6 +1480,144 @@ impl PostgresBuilder {
     /// Postgres will start using a newly created temporary directory as its data dir. The function
     /// will only return once `pg_isready` reports the server is accepting connections.
     pub fn start(&self) -> Result<Postgres, Error> {
+        let startup = self.prepare()?;
+
+        if let Some(initdb_dir) = startup.initdb_dir() {
+            let initdb_status = self
+                .initdb_command(&startup, &initdb_dir)
+                .status()
+                .map_err(Error::RunInitDb)?;
+            self.check_initdb(&startup, &initdb_dir, initdb_status)?;
+        }
+
+        let mut launched = self.launch(startup)?;
+        launched
+            .launcher
+            .wait_until_ready(launched.process.tmp_dir.path())?;
+
+        let reset_superuser_pw = launched.reset_superuser_pw.take();
+        let mut postgres = self.finish(launched);
+        if let Some(superuser_pw) = reset_superuser_pw {
+            execute_sql(
+                &postgres.psql_binary,
+                &postgres.superuser_url,
+                "postgres",
+                &self.reset_superuser_pw_sql(&superuser_pw),
+            )?;
+            postgres
+                .superuser_url
+                .set_password(Some(&superuser_pw))
+                .expect("Failed to set superuser password");
+        }
+
+        install_extensions(
+            &postgres.psql_binary,
+            &postgres.superuser_url,
+            "template1",
+            &self.extensions,
+        )?;
+
+        Ok(postgres)
+    }
+
+    /// Starts the Postgres server without blocking the async runtime.
+    ///
+    /// See [`PostgresBuilder::start`] for details.
+    #[cfg(feature = "async")]
+    pub async fn start_async(&self) -> Result<Postgres, Error> {
+        let startup = self.prepare()?;
+
+        if let Some(initdb_dir) = startup.initdb_dir() {
+            let initdb_status =
+                tokio::process::Command::from(self.initdb_command(&startup, &initdb_dir))
+                    .status()
+                    .await
+                    .map_err(Error::RunInitDb)?;
+            self.check_initdb(&startup, &initdb_dir, initdb_status)?;
+        }
+
+        let mut launched = self.launch(startup)?;
+
+        // Wait for the server to become ready to accept connections.
+        let started = Instant::now();
+        loop {
+            let pg_isready_command = launched
+                .launcher
+                .pg_isready_command(launched.process.tmp_dir.path());
+            match tokio::process::Command::from(pg_isready_command)
+                .status()
+                .await
+            {
+                Ok(exit_status) if exit_status.success() => break,
+                _ => {
+                    if started.elapsed() >= self.startup_timeout {
+                        return Err(Error::StartupTimeout);
+                    }
+                    tokio::time::sleep(self.probe_delay).await;
+                }
+            }
+        }
+
+        let reset_superuser_pw = launched.reset_superuser_pw.take();
+        let mut postgres = self.finish(launched);
+        if let Some(superuser_pw) = reset_superuser_pw {
+            execute_sql_async(
+                &postgres.psql_binary,
+                &postgres.superuser_url,
+                "postgres",
+                &self.reset_superuser_pw_sql(&superuser_pw),
+            )
+            .await?;
+            postgres
+                .superuser_url
+                .set_password(Some(&superuser_pw))
+                .expect("Failed to set superuser password");
+        }
+
+        if !self.extensions.is_empty() {
+            let psql_binary = postgres.psql_binary.clone();
+            let superuser_url = postgres.superuser_url.clone();
+            let extensions = self.extensions.clone();
+            tokio::task::spawn_blocking(move || {
+                install_extensions(&psql_binary, &superuser_url, "template1", &extensions)
+            })
+            .await
+            .expect("creating extensions panicked")?;
+        }
+
+        Ok(postgres)
+    }
+
+    /// Returns the value for `shared_preload_libraries`, if any libraries need to be preloaded.
+    ///
+    /// Combines libraries required by extensions with those set through
+    /// [`PostgresBuilder::postgres_option`].
+    fn shared_preload_libraries(&self) -> Option<String> {
+        let mut libraries: Vec<&str> = self
+            .postgres_options
+            .iter()
+            .filter(|(name, _)| name == "shared_preload_libraries")
+            .flat_map(|(_, value)| value.split(','))
+            .map(str::trim)
+            .filter(|library| !library.is_empty())
+            .collect();
+        for extension in &self.extensions {
+            if PRELOAD_EXTENSIONS.contains(&extension.as_str())
+                && !libraries.contains(&extension.as_str())
+            {
+                libraries.push(extension);
+            }
+        }
+
+        if libraries.is_empty() {
+            None
+        } else {
+            Some(libraries.join(","))
+        }
+    }
+
+    /// Resolves binaries and creates the temporary directory for a new server.
+    fn prepare(&self) -> Result<Startup, Error> {
         let port = if self.tcp {
             self.port
                 .unwrap_or_else(|| find_unused_port().expect("failed to find an unused port"))
@@ -519,72 +1625,459 @@ impl PostgresBuilder {
             DEFAULT_POSTGRES_PORT
         };
 
-        let postgres_binary = self
-            .postgres_binary
-            .clone()
-            .map(Ok)
-            .unwrap_or_else(|| which::which("postgres").map_err(Error::FindPostgres))?;
-        let initdb_binary = self
-            .initdb_binary
-            .clone()
-            .map(Ok)
-            .unwrap_or_else(|| which::which("initdb").map_err(Error::FindInitdb))?;
-        let pg_isready_binary = self
-            .pg_isready_binary
-            .clone()
-            .map(Ok)
-            .unwrap_or_else(|| which::which("pg_isready").map_err(Error::FindPgIsready))?;
-        let psql_binary = self
-            .psql_binary
-            .clone()
-            .map(Ok)
-            .unwrap_or_else(|| which::which("psql").map_err(Error::FindPsql))?;
+        let requirement = self
+            .version
+            .as_deref()
+            .map(str::parse::<discovery::VersionReq>)
+            .transpose()?;
+        let mut binaries = self.binaries.clone();
+        if let Some(requirement) = &requirement {
+            if !binaries.has_postgres() {
+                binaries.bin_dir(discovery::find(requirement)?.bin_dir());
+            }
+        }
 
-        let tmp_dir = tempfile::tempdir().map_err(Error::CreateDatabaseDir)?;
+        let postgres_binary = binaries.find_postgres()?;
+        let version = PgVersion::of_binary(&postgres_binary)?;
+        if let Some(requirement) = requirement {
+            if !requirement.matches(version) {
+                return Err(Error::PostgresVersionMismatch {
+                    required: requirement.to_string(),
+                    found: version,
+                });
+            }
+        }
+
+        let initdb_binary = binaries.find_initdb()?;
+        let pg_isready_binary = binaries.find_pg_isready()?;
+        let psql_binary = binaries.find_psql();
+        // The native client only needs `psql` for `PostgresClient::psql` and `load_sql`.
+        #[cfg(feature = "native-client")]
+        let psql_binary = psql_binary.or_else(|_| Ok::<_, Error>("psql".into()));
+        let psql_binary = psql_binary?;
+
+        if self.persistent && self.data_dir.is_none() {
+            return Err(Error::PersistentWithoutDataDir);
+        }
+
+        let tmp_dir = tempfile::tempdir().map_err(Error::CreateDatabaseDir)?;
+        #[cfg(feature = "tls")]
+        if self.tls {
+            let mut hosts = vec![
+                "localhost".to_string(),
+                "127.0.0.1".to_string(),
+                "::1".to_string(),
+            ];
+            if !hosts.contains(&self.host) {
+                hosts.push(self.host.clone());
+            }
+            tls::TlsFiles::new(tmp_dir.path()).generate(hosts)?;
+        }
+        fs::write(
+            tmp_dir.path().join(auth::HBA_FILE),
+            auth::hba_conf(self.auth, &self.superuser, &self.pg_hba_lines),
+        )
+        .map_err(Error::WriteHbaFile)?;
         let data_dir = self
             .data_dir
             .clone()
             .unwrap_or_else(|| tmp_dir.path().join("db"));
 
+        let initialized = self.persistent && data_dir.join("PG_VERSION").exists();
+        let superuser_pw = if initialized {
+            match fs::read_to_string(data_dir.join(STORED_SUPERUSER_PW_FILE)) {
+                Ok(stored_superuser_pw) => stored_superuser_pw,
+                // Clusters not created by `pgdb` have no stored password.
+                Err(err) if err.kind() == io::ErrorKind::NotFound => self.superuser_pw.clone(),
+                Err(err) => return Err(Error::ReadSuperuserPw(err)),
+            }
+        } else {
+            self.superuser_pw.clone()
+        };
+
         let superuser_pw_file = tmp_dir.path().join("superuser-pw");
-        fs::write(&superuser_pw_file, self.superuser_pw.as_bytes())
-            .map_err(Error::WriteTemporaryPw)?;
+        fs::write(&superuser_pw_file, superuser_pw.as_bytes()).map_err(Error::WriteTemporaryPw)?;
+
+        let mut startup = Startup {
+            port,
+            version,
+            postgres_binary,
+            initdb_binary,
+            pg_isready_binary,
+            psql_binary,
+            tmp_dir,
+            data_dir,
+            initialized,
+            cache_entry: None,
+            cached_superuser_pw: None,
+            superuser_pw,
+            superuser_pw_file,
+        };
 
-        let initdb_status = process::Command::new(initdb_binary)
-            .args([
+        if let Some(cache_dir) = &self.initdb_cache {
+            if !startup.initialized && !self.persistent {
+                // The cache is only an optimization, `initdb` runs as usual if it is unusable.
+                if let Err(err) = self.copy_cached_cluster(cache_dir, &mut startup) {
+                    eprintln!(
+                        "pgdb: not using initdb cache in {}: {}",
+                        cache_dir.display(),
+                        err
+                    );
+                }
+            }
+        }
+
+        Ok(startup)
+    }
+
+    /// Copies the cluster created by this builder from the cache into the data directory.
+    ///
+    /// If the cache has no such cluster yet, its entry is recorded to be populated after `initdb`.
+    fn copy_cached_cluster(
+        &self,
+        cache_dir: &path::Path,
+        startup: &mut Startup,
+    ) -> Result<(), io::Error> {
+        let entry_dir =
+            initdb_cache::entry_dir(cache_dir, &startup.initdb_binary, &self.initdb_args())?;
+        if !entry_dir.is_dir() {
+            startup.cache_entry = Some(entry_dir);
+            return Ok(());
+        }
+
+        let copied = copy_data_dir(&entry_dir, &startup.data_dir)
+            .and_then(|()| fs::read_to_string(startup.data_dir.join(STORED_SUPERUSER_PW_FILE)));
+        match copied {
+            Ok(cached_superuser_pw) => {
+                startup.initialized = true;
+                startup.cached_superuser_pw = Some(cached_superuser_pw);
+                Ok(())
+            }
+            Err(err) => {
+                // Leave an empty data directory behind for `initdb`.
+                let _ = fs::remove_dir_all(&startup.data_dir);
+                Err(err)
+            }
+        }
+    }
+
+    /// Stores a freshly initialized cluster in the cache entry at `entry_dir`.
+    fn populate_cache(&self, startup: &Startup, entry_dir: &path::Path) -> Result<(), io::Error> {
+        let cache_dir = entry_dir.parent().unwrap_or(entry_dir);
+        let staging_dir = initdb_cache::staging_dir(cache_dir)?;
+        let cluster = staging_dir.path().join("db");
+        copy_data_dir(&startup.data_dir, &cluster)?;
+        fs::write(
+            cluster.join(STORED_SUPERUSER_PW_FILE),
+            startup.superuser_pw.as_bytes(),
+        )?;
+        initdb_cache::insert(&cluster, entry_dir)
+    }
+
+    /// Checks the exit status of `initdb`.
+    ///
+    /// Stores the superuser password for persistent clusters and populates the cache, if enabled.
+    fn check_initdb(
+        &self,
+        startup: &Startup,
+        initdb_dir: &path::Path,
+        status: process::ExitStatus,
+    ) -> Result<(), Error> {
+        if !status.success() {
+            return Err(Error::InitDbFailed(status));
+        }
+
+        if self.persistent {
+            fs::write(
+                initdb_dir.join(STORED_SUPERUSER_PW_FILE),
+                startup.superuser_pw.as_bytes(),
+            )
+            .map_err(Error::StoreSuperuserPw)?;
+        }
+
+        if let Some(entry_dir) = &startup.cache_entry {
+            if let Err(err) = self.populate_cache(startup, entry_dir) {
+                eprintln!(
+                    "pgdb: could not store cluster in initdb cache at {}: {}",
+                    entry_dir.display(),
+                    err
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the `initdb` arguments that determine the contents of a new cluster.
+    fn initdb_args(&self) -> Vec<ffi::OsString> {
+        let mut args: Vec<ffi::OsString> = vec![
+            match &self.locale {
+                Some(locale) => format!("--locale={locale}").into(),
                 // No default locale (== 'C').
-                "--no-locale",
-                // Require a password for all users.
-                "--auth=md5",
-                // Set default encoding to UTF8.
-                "--encoding=UTF8",
-                // Do not sync data, which is fine for tests.
-                "--nosync",
-                // Path to data directory.
-                "--pgdata",
-            ])
-            .arg(&data_dir)
+                None => "--no-locale".into(),
+            },
+            // Require a password for all users, replaced by the generated `hba_file` on startup.
+            "--auth=scram-sha-256".into(),
+            format!("--encoding={}", self.encoding).into(),
+            "--username".into(),
+            self.superuser.clone().into(),
+        ];
+        if let Some(lc_collate) = &self.lc_collate {
+            args.push(format!("--lc-collate={lc_collate}").into());
+        }
+        if let Some(lc_ctype) = &self.lc_ctype {
+            args.push(format!("--lc-ctype={lc_ctype}").into());
+        }
+        if let Some(icu_locale) = &self.icu_locale {
+            args.push("--locale-provider=icu".into());
+            args.push(format!("--icu-locale={icu_locale}").into());
+        }
+        if self.data_checksums {
+            args.push("--data-checksums".into());
+        }
+        args.extend(self.extra_initdb_args.iter().cloned());
+        args
+    }
+
+    /// Creates the `initdb` command initializing `initdb_dir`.
+    fn initdb_command(&self, startup: &Startup, initdb_dir: &path::Path) -> process::Command {
+        let mut initdb_command = process::Command::new(&startup.initdb_binary);
+        initdb_command
+            .args(self.initdb_args())
+            // Do not sync data, which is fine for tests.
+            .arg("--nosync")
+            // Path to data directory.
+            .arg("--pgdata")
+            .arg(initdb_dir)
             .arg("--pwfile")
-            .arg(&superuser_pw_file)
-            .arg("--username")
-            .arg(&self.superuser)
-            .status()
-            .map_err(Error::RunInitDb)?;
+            .arg(&startup.superuser_pw_file)
+            // Keep stdout clean for callers printing connection details, errors go to stderr.
+            .stdout(process::Stdio::null());
+        initdb_command
+    }
+
+    /// Returns SQL changing the superuser password of a cluster copied from the cache.
+    fn reset_superuser_pw_sql(&self, superuser_pw: &str) -> String {
+        format!(
+            "ALTER ROLE {} WITH PASSWORD {};",
+            escape_ident(&self.superuser),
+            escape_string(superuser_pw)
+        )
+    }
+
+    /// Includes the configured files from the `postgresql.conf` in `data_dir`.
+    ///
+    /// The includes are kept in a separate file rewritten on every start, so persistent clusters
+    /// pick up changes to the configured files. Clusters that never had any files configured are
+    /// left untouched.
+    fn write_config_includes(&self, data_dir: &path::Path) -> Result<(), Error> {
+        let include_line = format!("include_if_exists '{CONFIG_INCLUDES_FILE}'\n");
+        let config_path = data_dir.join("postgresql.conf");
+        let config = fs::read_to_string(&config_path).map_err(Error::WriteConfigIncludes)?;
+        let included = config.contains(&include_line);
+        if self.config_files.is_empty() && !included {
+            return Ok(());
+        }
+
+        let mut includes = String::new();
+        for config_file in &self.config_files {
+            let path = fs::canonicalize(config_file).map_err(|source| Error::FindConfigFile {
+                path: config_file.clone(),
+                source,
+            })?;
+            includes.push_str(&format!(
+                "include {}\n",
+                escape_string(&path.to_string_lossy())
+            ));
+        }
+        fs::write(data_dir.join(CONFIG_INCLUDES_FILE), includes)
+            .map_err(Error::WriteConfigIncludes)?;
+
+        if !included {
+            fs::OpenOptions::new()
+                .append(true)
+                .open(&config_path)
+                .and_then(|mut file| io::Write::write_all(&mut file, include_line.as_bytes()))
+                .map_err(Error::WriteConfigIncludes)?;
+        }
+
+        Ok(())
+    }
+
+    /// Launches the `postgres` process on an initialized data directory.
+    fn launch(&self, startup: Startup) -> Result<Launched, Error> {
+        let mut superuser_pw = startup.superuser_pw;
+        let mut reset_superuser_pw = None;
+        if let Some(cached_superuser_pw) = startup.cached_superuser_pw {
+            if cached_superuser_pw != superuser_pw {
+                reset_superuser_pw =
+                    Some(std::mem::replace(&mut superuser_pw, cached_superuser_pw));
+            }
+        }
+        self.write_config_includes(&startup.data_dir)?;
+
+        let launcher = Launcher {
+            builder: self.clone(),
+            version: startup.version,
+            postgres_binary: startup.postgres_binary,
+            pg_isready_binary: startup.pg_isready_binary,
+            data_dir: startup.data_dir,
+            port: startup.port,
+            log_path: if self.log_to_file {
+                Some(startup.tmp_dir.path().join(LOG_FILE))
+            } else {
+                None
+            },
+        };
+        let instance = launcher.spawn(startup.tmp_dir.path())?;
+
+        Ok(Launched {
+            process: PostgresProcess {
+                instance,
+                tmp_dir: startup.tmp_dir,
+            },
+            launcher,
+            psql_binary: startup.psql_binary,
+            superuser_pw,
+            reset_superuser_pw,
+        })
+    }
+
+    /// Wraps a launched server that is ready to accept connections.
+    fn finish(&self, launched: Launched) -> Postgres {
+        let Launched {
+            process,
+            launcher,
+            psql_binary,
+            superuser_pw,
+            reset_superuser_pw: _,
+        } = launched;
+        let port = launcher.port;
+
+        let mut superuser_url = if self.tcp {
+            Url::parse(&format!("postgres://{}:{}", self.host, port))
+                .expect("Failed to construct TCP URL")
+        } else {
+            let socket_dir = process.tmp_dir.path().to_string_lossy();
+            let encoded_socket_dir = utf8_percent_encode(&socket_dir, NON_ALPHANUMERIC).to_string();
+            Url::parse(&format!("postgres://{encoded_socket_dir}:{port}"))
+                .expect("Failed to construct Unix socket URL")
+        };
+        superuser_url
+            .set_username(&self.superuser)
+            .expect("Failed to set superuser username");
+        superuser_url
+            .set_password(Some(&superuser_pw))
+            .expect("Failed to set superuser password");
+        #[cfg(feature = "tls")]
+        if self.tls {
+            let ca_cert = tls::TlsFiles::new(process.tmp_dir.path()).ca_cert;
+            superuser_url
+                .query_pairs_mut()
+                .append_pair("sslmode", "verify-full")
+                .append_pair("sslrootcert", &ca_cert.to_string_lossy());
+        }
 
-        if !initdb_status.success() {
-            return Err(Error::InitDbFailed(initdb_status));
+        Postgres {
+            superuser_url,
+            process,
+            launcher,
+            psql_binary,
+            cleanup: Mutex::new(None),
+            primary_url: None,
         }
+    }
+}
+
+/// A server that is about to be initialized and launched by [`PostgresBuilder`].
+struct Startup {
+    /// Port to listen on, also used for the Unix socket name.
+    port: u16,
+    /// Version of the `postgres` binary.
+    version: PgVersion,
+    /// Path to `postgres` binary.
+    postgres_binary: path::PathBuf,
+    /// Path to `initdb` binary.
+    initdb_binary: path::PathBuf,
+    /// Path to `pg_isready` binary.
+    pg_isready_binary: path::PathBuf,
+    /// Path to `psql` binary.
+    psql_binary: path::PathBuf,
+    /// Directory holding all temporary data.
+    tmp_dir: tempfile::TempDir,
+    /// Data directory.
+    data_dir: path::PathBuf,
+    /// Whether the data directory already contains a cluster that should be reused.
+    initialized: bool,
+    /// Cache entry to store the cluster in once `initdb` created it.
+    cache_entry: Option<path::PathBuf>,
+    /// Superuser password of a cluster copied from the cache.
+    cached_superuser_pw: Option<String>,
+    /// Password for the superuser.
+    superuser_pw: String,
+    /// File containing the superuser password, read by `initdb`.
+    superuser_pw_file: path::PathBuf,
+}
+
+impl Startup {
+    /// Returns the directory `initdb` needs to initialize, if any.
+    fn initdb_dir(&self) -> Option<path::PathBuf> {
+        if self.initialized {
+            None
+        } else {
+            Some(self.data_dir.clone())
+        }
+    }
+}
+
+/// A launched server that may not be ready to accept connections yet.
+struct Launched {
+    /// PostgreSQL process and its temporary directory.
+    process: PostgresProcess,
+    /// Configuration the process was launched with.
+    launcher: Launcher,
+    /// Path to `psql` binary.
+    psql_binary: path::PathBuf,
+    /// Password for the superuser.
+    superuser_pw: String,
+    /// Password to set for the superuser once the server is ready.
+    reset_superuser_pw: Option<String>,
+}
+
+/// Everything needed to (re)launch `postgres` on an initialized data directory.
+#[derive(Debug)]
+struct Launcher {
+    /// Settings of the builder that created the server.
+    builder: PostgresBuilder,
+    /// Version of the `postgres` binary.
+    version: PgVersion,
+    /// Path to `postgres` binary.
+    postgres_binary: path::PathBuf,
+    /// Path to `pg_isready` binary.
+    pg_isready_binary: path::PathBuf,
+    /// Data directory.
+    data_dir: path::PathBuf,
+    /// Port the server listens on.
+    port: u16,
+    /// File receiving the server's output, if any.
+    log_path: Option<path::PathBuf>,
+}
+
+impl Launcher {
+    /// Spawns the `postgres` process, placing its Unix socket in `socket_dir`.
+    fn spawn(&self, socket_dir: &path::Path) -> Result<ProcessGuard, Error> {
+        let builder = &self.builder;
 
         // Start the database.
-        let mut postgres_command = process::Command::new(postgres_binary);
+        let mut postgres_command = process::Command::new(&self.postgres_binary);
         postgres_command
             .arg("-D")
-            .arg(&data_dir)
+            .arg(&self.data_dir)
             .arg("-p")
-            .arg(port.to_string())
+            .arg(self.port.to_string())
             .arg("-k")
-            .arg(tmp_dir.path());
-        if self.fast {
+            .arg(socket_dir);
+        if builder.fast {
             for option in [
                 "fsync=off",
                 "synchronous_commit=off",
@@ -593,176 +2086,901 @@ impl PostgresBuilder {
                 postgres_command.arg("-c").arg(option);
             }
         }
-        for (name, value) in &self.postgres_options {
-            postgres_command.arg("-c").arg(format!("{name}={value}"));
+        postgres_command.arg("-c").arg(format!(
+            "hba_file={}",
+            socket_dir.join(auth::HBA_FILE).display()
+        ));
+        if builder.auth == AuthMethod::Md5 {
+            postgres_command.arg("-c").arg("password_encryption=md5");
         }
-        if self.tcp {
-            postgres_command.arg("-h").arg(&self.host);
+        for (name, value) in &builder.postgres_options {
+            if name != "shared_preload_libraries" {
+                postgres_command.arg("-c").arg(format!("{name}={value}"));
+            }
+        }
+        if let Some(libraries) = builder.shared_preload_libraries() {
+            postgres_command
+                .arg("-c")
+                .arg(format!("shared_preload_libraries={libraries}"));
+        }
+        if builder.tcp {
+            postgres_command.arg("-h").arg(&builder.host);
         } else {
             postgres_command.arg("-c").arg("listen_addresses=");
         }
+        #[cfg(feature = "tls")]
+        if builder.tls {
+            let tls_files = tls::TlsFiles::new(socket_dir);
+            postgres_command
+                .args(["-c", "ssl=on", "-c"])
+                .arg(format!("ssl_cert_file={}", tls_files.server_cert.display()))
+                .arg("-c")
+                .arg(format!("ssl_key_file={}", tls_files.server_key.display()))
+                .arg("-c")
+                .arg(format!("ssl_ca_file={}", tls_files.ca_cert.display()));
+        }
+        if let Some(log_path) = &self.log_path {
+            // Appending keeps the output from before restarts.
+            let log = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(log_path)
+                .map_err(Error::OpenLog)?;
+            let log_stderr = log.try_clone().map_err(Error::OpenLog)?;
+            postgres_command.stdout(log).stderr(log_stderr);
+        }
 
-        let instance = ProcessGuard::spawn_process_group(
+        ProcessGuard::spawn_process_group(
             &mut postgres_command,
             ShutdownPolicy::Graceful {
-                signal: if self.fast {
+                signal: if builder.fast {
                     Signal::SIGQUIT
                 } else {
                     Signal::SIGINT
                 },
-                grace_time: self.shutdown_timeout,
-                force_time: self.force_shutdown_timeout,
+                grace_time: builder.shutdown_timeout,
+                force_time: builder.force_shutdown_timeout,
             },
         )
-        .map_err(Error::LaunchPostgres)?;
-        let process = PostgresProcess { instance, tmp_dir };
+        .map_err(Error::LaunchPostgres)
+    }
+
+    /// Creates a `pg_isready` command probing whether the server accepts connections.
+    fn pg_isready_command(&self, socket_dir: &path::Path) -> process::Command {
+        let mut pg_isready_command = process::Command::new(&self.pg_isready_binary);
+        pg_isready_command.arg("-h");
+        if self.builder.tcp {
+            pg_isready_command.arg(&self.builder.host);
+        } else {
+            pg_isready_command.arg(socket_dir);
+        }
+        pg_isready_command
+            .arg("-p")
+            .arg(self.port.to_string())
+            .stdout(process::Stdio::null())
+            .stderr(process::Stdio::null());
+        pg_isready_command
+    }
+
+    /// Waits for the server to become ready to accept connections.
+    fn wait_until_ready(&self, socket_dir: &path::Path) -> Result<(), Error> {
+        let started = Instant::now();
+        loop {
+            match self.pg_isready_command(socket_dir).status() {
+                Ok(exit_status) if exit_status.success() => return Ok(()),
+                _ => {
+                    if started.elapsed() >= self.builder.startup_timeout {
+                        return Err(Error::StartupTimeout);
+                    }
+                    thread::sleep(self.builder.probe_delay);
+                }
+            }
+        }
+    }
+}
+
+/// Replaces `destination` with a copy of the stopped cluster's data directory `source`.
+///
+/// The copy is made next to `destination` and renamed into place, so `destination` is left
+/// untouched if copying fails.
+fn replace_with_copy(source: &path::Path, destination: &path::Path) -> io::Result<()> {
+    let parent = destination
+        .parent()
+        .ok_or_else(|| io::Error::other("destination has no parent directory"))?;
+    fs::create_dir_all(parent)?;
+    let staging_dir = tempfile::Builder::new()
+        .prefix(".pgdb-staging-")
+        .tempdir_in(parent)?;
+    let copy = staging_dir.path().join("copy");
+    copy_data_dir(source, &copy)?;
+
+    // The previous contents are removed along with the staging directory.
+    let previous = staging_dir.path().join("previous");
+    let replaced = destination.exists();
+    if replaced {
+        fs::rename(destination, &previous)?;
+    }
+    if let Err(err) = fs::rename(&copy, destination) {
+        if replaced {
+            let _ = fs::rename(&previous, destination);
+        }
+        return Err(err);
+    }
+
+    Ok(())
+}
+
+/// Recursively copies a stopped cluster's data directory.
+///
+/// Relies on [`fs::copy`], which uses copy-on-write clones where the filesystem supports them.
+fn copy_data_dir(source: &path::Path, destination: &path::Path) -> io::Result<()> {
+    fs::create_dir_all(destination)?;
+    // `initdb` creates data directories that are only accessible by their owner.
+    fs::set_permissions(destination, fs::metadata(source)?.permissions())?;
+
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        // A stale PID file would prevent the copy from starting.
+        if entry.file_name() == "postmaster.pid" {
+            continue;
+        }
+
+        let target = destination.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_symlink() {
+            // Tablespaces and a relocated `pg_wal` are symlinks, which must keep pointing there.
+            std::os::unix::fs::symlink(fs::read_link(entry.path())?, target)?;
+        } else if file_type.is_dir() {
+            copy_data_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Generates a random hex string 32 characters long.
+fn generate_random_string() -> String {
+    let raw: [u8; 16] = rand::random();
+    format!("{:x}", hex_fmt::HexFmt(&raw))
+}
+
+/// Escapes an identifier by wrapping in quote char. Any quote character inside the unescaped string
+/// will be doubled.
+fn quote(quote_char: char, unescaped: &str) -> String {
+    let mut result = String::new();
+
+    result.push(quote_char);
+    for c in unescaped.chars() {
+        if c == quote_char {
+            result.push(quote_char);
+            result.push(quote_char);
+        } else {
+            result.push(c);
+        }
+    }
+    result.push(quote_char);
+
+    result
+}
+
+/// Escapes an identifier.
+fn escape_ident(unescaped: &str) -> String {
+    quote('"', unescaped)
+}
+
+/// Escapes a string.
+fn escape_string(unescaped: &str) -> String {
+    quote('\'', unescaped)
+}
+
+/// Parses the `PGDB_TESTS_URL` environment variable if set.
+///
+/// The URL must be a complete Postgres URL with superuser credentials.
+///
+/// Returns `Ok(Some(url))` if valid, `Ok(None)` if not set, or `Err` if invalid.
+pub fn parse_external_test_url() -> Result<Option<Url>, Error> {
+    match env::var("PGDB_TESTS_URL") {
+        Ok(url_str) => {
+            let url = Url::parse(&url_str)
+                .map_err(|e| Error::InvalidExternalUrl(ExternalUrlError::ParseError(e)))?;
+
+            if url.scheme() != "postgres" {
+                return Err(Error::InvalidExternalUrl(ExternalUrlError::InvalidScheme));
+            }
+
+            if url.host_str().is_none() {
+                return Err(Error::InvalidExternalUrl(ExternalUrlError::MissingHost));
+            }
+
+            if url.username().is_empty() {
+                return Err(Error::InvalidExternalUrl(ExternalUrlError::MissingUsername));
+            }
+
+            Ok(Some(url))
+        }
+        Err(_) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::{Duration, Instant},
+    };
+
+    use url::Url;
+
+    use super::{AuthMethod, Postgres};
+
+    #[test]
+    fn connection_parameters_decode_socket_urls() {
+        let url =
+            Url::parse("postgres://dev@%2Ftmp%2Fpgdb:5432/dev").expect("socket URL must be valid");
+
+        assert_eq!(super::connection_host(&url).as_deref(), Some("/tmp/pgdb"));
+        assert_eq!(super::connection_port(&url), Some(5432));
+    }
+
+    #[test]
+    fn can_change_superuser_pw() {
+        let pg = Postgres::build()
+            .superuser_pw("helloworld")
+            .start()
+            .expect("could not build postgres database");
+
+        let su = pg.as_superuser();
+        su.create_user("foo", "bar")
+            .expect("could not create normal user");
+
+        // Command executed successfully, check we used the right password.
+        assert_eq!(su.client_url().password(), Some("helloworld"));
+    }
+
+    #[test]
+    fn run_sql_reports_failing_statements() {
+        let pg = Postgres::build()
+            .start()
+            .expect("could not build postgres database");
+        let su = pg.as_superuser();
+
+        su.run_sql(
+            "postgres",
+            "CREATE TABLE foo (id INT); INSERT INTO foo VALUES (1);",
+        )
+        .expect("valid statements must succeed");
+        assert!(su
+            .run_sql("postgres", "SELECT * FROM missing_table;")
+            .is_err());
+    }
+
+    #[test]
+    fn load_sql_reports_error_location() {
+        let pg = Postgres::build()
+            .start()
+            .expect("could not build postgres database");
+        let sql_file = tempfile::NamedTempFile::new().expect("could not create SQL file");
+        std::fs::write(
+            sql_file.path(),
+            "CREATE TABLE foo (id INT);\n\nSELECT * FROM missing_table;\n",
+        )
+        .expect("could not write SQL file");
+
+        match pg.as_superuser().load_sql("postgres", sql_file.path()) {
+            Err(crate::Error::PsqlFailed(failure)) => {
+                assert_eq!(failure.sqlstate(), Some("42P01"));
+                assert!(failure.message().contains("missing_table"));
+                assert_eq!(failure.file(), Some(sql_file.path()));
+                assert_eq!(failure.line(), Some(3));
+            }
+            other => panic!("expected psql failure, got {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn async_startup_sql_and_fixtures() {
+        let pg = Postgres::build()
+            .start_async()
+            .await
+            .expect("could not build postgres database");
+        let su = pg.as_superuser();
+
+        su.run_sql_async("postgres", "CREATE TABLE foo (id INT);")
+            .await
+            .expect("valid statements must succeed");
+        assert!(su
+            .run_sql_async("postgres", "SELECT * FROM missing_table;")
+            .await
+            .is_err());
+
+        let db_url = crate::db_fixture_async().await;
+        assert!(db_url.as_str().contains("fixture_db_"));
+    }
+
+    #[test]
+    fn persistent_clusters_survive_drop() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let data_dir = dir.path().join("data");
+
+        let pg = Postgres::build()
+            .data_dir(&data_dir)
+            .persistent()
+            .superuser_pw("first")
+            .start()
+            .expect("could not build postgres database");
+        pg.as_superuser()
+            .create_user("dev", "dev")
+            .expect("could not create normal user");
+        drop(pg);
+
+        assert!(data_dir.join("PG_VERSION").exists());
+
+        let pg = Postgres::build()
+            .data_dir(&data_dir)
+            .persistent()
+            .superuser_pw("second")
+            .start()
+            .expect("could not restart persistent postgres database");
+        let su = pg.as_superuser();
+        assert_eq!(su.client_url().password(), Some("first"));
+        assert!(su.user_exists("dev").expect("could not query users"));
+        assert!(!su
+            .database_exists("dev")
+            .expect("could not query databases"));
+        drop(pg);
+
+        // Without a stored password, e.g. for clusters created elsewhere, the configured one is used.
+        std::fs::remove_file(data_dir.join(super::STORED_SUPERUSER_PW_FILE))
+            .expect("could not remove stored password");
+        let pg = Postgres::build()
+            .data_dir(&data_dir)
+            .persistent()
+            .superuser_pw("first")
+            .start()
+            .expect("could not start cluster without stored password");
+        assert!(pg
+            .as_superuser()
+            .user_exists("dev")
+            .expect("could not query users"));
+    }
+
+    #[test]
+    fn initdb_settings_are_applied() {
+        let pg = Postgres::build()
+            .encoding("LATIN1")
+            .icu_locale("en-US")
+            .data_checksums()
+            .start()
+            .expect("could not build postgres database");
+        let superuser = pg.as_superuser();
+        let show = |setting: &str| {
+            superuser
+                .query_scalar("postgres", &format!("SHOW {setting}"))
+                .expect("could not query setting")
+        };
+
+        assert_eq!(show("server_encoding").as_deref(), Some("LATIN1"));
+        assert_eq!(show("data_checksums").as_deref(), Some("on"));
+        // ICU sorts case-insensitively first, unlike the `C` locale.
+        let sorted = superuser
+            .query_scalar(
+                "postgres",
+                "SELECT string_agg(x, '' ORDER BY x) FROM (VALUES ('a'), ('B')) AS v(x)",
+            )
+            .expect("could not sort");
+        assert_eq!(sorted.as_deref(), Some("aB"));
+    }
+
+    #[test]
+    fn initdb_args_and_config_files_are_applied() {
+        let config_dir = tempfile::tempdir().expect("could not create config directory");
+        let config_file = config_dir.path().join("app's.conf");
+        std::fs::write(&config_file, "work_mem = '12MB'\n").expect("could not write config file");
+
+        let pg = Postgres::build()
+            .initdb_arg("--wal-segsize=32")
+            .config_file(&config_file)
+            .start()
+            .expect("could not build postgres database");
+        let superuser = pg.as_superuser();
+        let show = |setting: &str| {
+            superuser
+                .query_scalar("postgres", &format!("SHOW {setting}"))
+                .expect("could not query setting")
+        };
+
+        assert_eq!(show("wal_segment_size").as_deref(), Some("32MB"));
+        assert_eq!(show("work_mem").as_deref(), Some("12MB"));
+
+        assert!(matches!(
+            Postgres::build()
+                .config_file(config_dir.path().join("missing.conf"))
+                .start(),
+            Err(crate::Error::FindConfigFile { .. })
+        ));
+    }
+
+    #[test]
+    fn config_includes_only_touch_clusters_using_them() {
+        let data_dir = tempfile::tempdir().expect("could not create data directory");
+        let config_path = data_dir.path().join("postgresql.conf");
+        let includes_path = data_dir.path().join(super::CONFIG_INCLUDES_FILE);
+        std::fs::write(&config_path, "port = 5432\n").expect("could not write config");
+
+        Postgres::build()
+            .write_config_includes(data_dir.path())
+            .expect("could not write includes");
+        assert_eq!(
+            std::fs::read_to_string(&config_path).expect("could not read config"),
+            "port = 5432\n"
+        );
+        assert!(!includes_path.exists());
+
+        Postgres::build()
+            .config_file(&config_path)
+            .write_config_includes(data_dir.path())
+            .expect("could not write includes");
+        let config = std::fs::read_to_string(&config_path).expect("could not read config");
+        assert!(config.ends_with("include_if_exists 'pgdb-includes.conf'\n"));
+        assert!(std::fs::read_to_string(&includes_path)
+            .expect("could not read includes")
+            .starts_with("include "));
+
+        // Dropping all files clears the includes, leaving `postgresql.conf` alone.
+        Postgres::build()
+            .write_config_includes(data_dir.path())
+            .expect("could not write includes");
+        assert_eq!(
+            std::fs::read_to_string(&config_path).expect("could not read config"),
+            config
+        );
+        assert_eq!(
+            std::fs::read_to_string(&includes_path).expect("could not read includes"),
+            ""
+        );
+    }
 
-        // Wait for the server to become ready to accept connections.
-        let started = Instant::now();
-        loop {
-            let mut pg_isready_command = process::Command::new(&pg_isready_binary);
-            pg_isready_command.arg("-h");
-            if self.tcp {
-                pg_isready_command.arg(&self.host);
-            } else {
-                pg_isready_command.arg(process.tmp_dir.path());
-            }
-            let status = pg_isready_command
-                .arg("-p")
-                .arg(port.to_string())
-                .stdout(process::Stdio::null())
-                .stderr(process::Stdio::null())
-                .status();
+    #[test]
+    fn replicas_replay_changes_and_can_be_promoted() {
+        let pg = Postgres::build()
+            .fast()
+            .start()
+            .expect("could not build postgres database");
+        let mut replica = pg.start_replica().expect("could not start replica");
+        assert_ne!(replica.superuser_url(), pg.superuser_url());
+
+        pg.as_superuser()
+            .run_sql(
+                "postgres",
+                "CREATE TABLE replicated (id INT); INSERT INTO replicated VALUES (1);",
+            )
+            .expect("could not write to primary");
+        replica
+            .wait_for_replay(Duration::from_secs(10))
+            .expect("replica did not catch up");
+        let count = replica
+            .as_superuser()
+            .query_scalar("postgres", "SELECT count(*) FROM replicated")
+            .expect("could not query replica");
+        assert_eq!(count.as_deref(), Some("1"));
+        assert!(replica
+            .as_superuser()
+            .run_sql("postgres", "INSERT INTO replicated VALUES (2);")
+            .is_err());
 
-            match status {
-                Ok(exit_status) if exit_status.success() => break,
-                _ => {
-                    if started.elapsed() >= self.startup_timeout {
-                        return Err(Error::StartupTimeout);
-                    }
-                    thread::sleep(self.probe_delay);
-                }
-            }
-        }
+        replica.promote().expect("could not promote replica");
+        replica
+            .as_superuser()
+            .run_sql("postgres", "INSERT INTO replicated VALUES (2);")
+            .expect("could not write to promoted replica");
+        assert!(matches!(
+            replica.wait_for_replay(Duration::from_secs(1)),
+            Err(crate::Error::NotAReplica)
+        ));
+    }
 
-        let mut superuser_url = if self.tcp {
-            Url::parse(&format!("postgres://{}:{}", self.host, port))
-                .expect("Failed to construct TCP URL")
-        } else {
-            let socket_dir = process.tmp_dir.path().to_string_lossy();
-            let encoded_socket_dir = utf8_percent_encode(&socket_dir, NON_ALPHANUMERIC).to_string();
-            Url::parse(&format!("postgres://{encoded_socket_dir}:{port}"))
-                .expect("Failed to construct Unix socket URL")
-        };
-        superuser_url
-            .set_username(&self.superuser)
-            .expect("Failed to set superuser username");
-        superuser_url
-            .set_password(Some(&self.superuser_pw))
-            .expect("Failed to set superuser password");
+    #[test]
+    fn replicas_can_cascade() {
+        // Standbys refuse base backups of WAL written with `full_page_writes=off`, so no `fast()`.
+        let pg = Postgres::build()
+            .start()
+            .expect("could not build postgres database");
+        let replica = pg.start_replica().expect("could not start replica");
+        let cascaded = replica
+            .start_replica()
+            .expect("could not start cascading replica");
+
+        pg.as_superuser()
+            .run_sql(
+                "postgres",
+                "CREATE TABLE replicated (id INT); INSERT INTO replicated VALUES (1);",
+            )
+            .expect("could not write to primary");
+        replica
+            .wait_for_replay(Duration::from_secs(10))
+            .expect("replica did not catch up");
+        cascaded
+            .wait_for_replay(Duration::from_secs(10))
+            .expect("cascading replica did not catch up");
+        let count = cascaded
+            .as_superuser()
+            .query_scalar("postgres", "SELECT count(*) FROM replicated")
+            .expect("could not query cascading replica");
+        assert_eq!(count.as_deref(), Some("1"));
+    }
 
-        Ok(Postgres {
-            superuser_url,
-            process,
-            psql_binary,
-        })
+    #[test]
+    fn replicas_preload_extension_libraries() {
+        let pg = Postgres::build()
+            .extension("pg_stat_statements")
+            .start()
+            .expect("could not build postgres database");
+        let replica = pg.start_replica().expect("could not start replica");
+
+        let su = replica.as_superuser();
+        assert_eq!(
+            su.query_scalar("postgres", "SHOW shared_preload_libraries")
+                .expect("could not query settings")
+                .as_deref(),
+            Some("pg_stat_statements")
+        );
+        // Extensions are only created in `template1` and databases created afterwards.
+        su.query("template1", "SELECT * FROM pg_stat_statements LIMIT 1")
+            .expect("pg_stat_statements must be usable on the replica");
     }
-}
 
-/// Generates a random hex string 32 characters long.
-fn generate_random_string() -> String {
-    let raw: [u8; 16] = rand::random();
-    format!("{:x}", hex_fmt::HexFmt(&raw))
-}
+    #[test]
+    fn initdb_cache_copies_pristine_clusters() {
+        let cache_dir = tempfile::tempdir().expect("could not create temporary directory");
 
-/// Escapes an identifier by wrapping in quote char. Any quote character inside the unescaped string
-/// will be doubled.
-fn quote(quote_char: char, unescaped: &str) -> String {
-    let mut result = String::new();
+        let pg = Postgres::build()
+            .initdb_cache_dir(cache_dir.path())
+            .superuser_pw("first")
+            .start()
+            .expect("could not build postgres database");
+        pg.as_superuser()
+            .create_user("dev", "dev")
+            .expect("could not create normal user");
+        drop(pg);
 
-    result.push(quote_char);
-    for c in unescaped.chars() {
-        if c == quote_char {
-            result.push(quote_char);
-            result.push(quote_char);
-        } else {
-            result.push(c);
-        }
+        let entries: Vec<_> = std::fs::read_dir(cache_dir.path())
+            .expect("could not read cache directory")
+            .collect();
+        assert_eq!(entries.len(), 1);
+
+        let pg = Postgres::build()
+            .initdb_cache_dir(cache_dir.path())
+            .superuser_pw("second")
+            .start()
+            .expect("could not build cached postgres database");
+        let su = pg.as_superuser();
+        assert_eq!(su.client_url().password(), Some("second"));
+        assert!(!su.user_exists("dev").expect("could not query users"));
     }
-    result.push(quote_char);
 
-    result
-}
+    #[test]
+    fn unusable_initdb_cache_falls_back_to_initdb() {
+        let cache_file = tempfile::NamedTempFile::new().expect("could not create temporary file");
 
-/// Escapes an identifier.
-fn escape_ident(unescaped: &str) -> String {
-    quote('"', unescaped)
-}
+        let pg = Postgres::build()
+            .initdb_cache_dir(cache_file.path())
+            .start()
+            .expect("could not build postgres database");
+        assert!(pg
+            .as_superuser()
+            .query_scalar("postgres", "SELECT 1")
+            .expect("could not query server")
+            .is_some());
+    }
 
-/// Escapes a string.
-fn escape_string(unescaped: &str) -> String {
-    quote('\'', unescaped)
-}
+    #[test]
+    fn version_requirement_selects_installation() {
+        let pg = Postgres::build()
+            .version(">=10")
+            .start()
+            .expect("could not start a PostgreSQL 10 or newer");
+        let version_num = pg
+            .as_superuser()
+            .query_scalar("postgres", "SHOW server_version_num")
+            .expect("could not query version")
+            .expect("version must be set");
+        assert_eq!(
+            pg.server_version().major(),
+            version_num.parse::<u32>().expect("version must be numeric") / 10000
+        );
 
-/// Parses the `PGDB_TESTS_URL` environment variable if set.
-///
-/// The URL must be a complete Postgres URL with superuser credentials.
-///
-/// Returns `Ok(Some(url))` if valid, `Ok(None)` if not set, or `Err` if invalid.
-pub fn parse_external_test_url() -> Result<Option<Url>, Error> {
-    match env::var("PGDB_TESTS_URL") {
-        Ok(url_str) => {
-            let url = Url::parse(&url_str)
-                .map_err(|e| Error::InvalidExternalUrl(ExternalUrlError::ParseError(e)))?;
+        let result = Postgres::build().version("<10").start();
+        assert!(matches!(result, Err(crate::Error::NoMatchingPostgres(_))));
+    }
 
-            if url.scheme() != "postgres" {
-                return Err(Error::InvalidExternalUrl(ExternalUrlError::InvalidScheme));
-            }
+    #[test]
+    fn snapshots_restore_entire_cluster() {
+        let mut pg = Postgres::build()
+            .fast()
+            .start()
+            .expect("could not build postgres database");
+        pg.as_superuser()
+            .create_user("before", "before")
+            .expect("could not create user");
 
-            if url.host_str().is_none() {
-                return Err(Error::InvalidExternalUrl(ExternalUrlError::MissingHost));
-            }
+        pg.snapshot("clean").expect("could not take snapshot");
+        let su = pg.as_superuser();
+        su.create_user("after", "after")
+            .expect("could not create user");
+        su.create_database("after", "after")
+            .expect("could not create database");
 
-            if url.username().is_empty() {
-                return Err(Error::InvalidExternalUrl(ExternalUrlError::MissingUsername));
-            }
+        pg.restore("clean").expect("could not restore snapshot");
+        let su = pg.as_superuser();
+        assert!(su.user_exists("before").expect("could not query users"));
+        assert!(!su.user_exists("after").expect("could not query users"));
+        assert!(!su
+            .database_exists("after")
+            .expect("could not query databases"));
+
+        assert!(matches!(
+            pg.restore("missing"),
+            Err(crate::Error::UnknownSnapshot(_))
+        ));
+        assert!(matches!(
+            pg.snapshot("../escape"),
+            Err(crate::Error::InvalidSnapshotName(_))
+        ));
+    }
 
-            Ok(Some(url))
-        }
-        Err(_) => Ok(None),
+    #[test]
+    fn data_dir_copies_keep_symlinks_and_replace_atomically() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let source = dir.path().join("source");
+        std::fs::create_dir_all(source.join("base")).expect("could not create source");
+        std::fs::write(source.join("base").join("1"), "data").expect("could not write file");
+        std::os::unix::fs::symlink("/nonexistent/tablespace", source.join("tablespace"))
+            .expect("could not create symlink");
+
+        let destination = dir.path().join("destination");
+        std::fs::create_dir(&destination).expect("could not create destination");
+        std::fs::write(destination.join("old"), "old").expect("could not write file");
+
+        super::replace_with_copy(&source, &destination).expect("could not copy data dir");
+        assert_eq!(
+            std::fs::read_link(destination.join("tablespace")).expect("symlink must be kept"),
+            path::Path::new("/nonexistent/tablespace")
+        );
+        assert!(destination.join("base").join("1").exists());
+        assert!(!destination.join("old").exists());
+
+        // A failed copy leaves the destination alone.
+        assert!(super::replace_with_copy(&dir.path().join("missing"), &destination).is_err());
+        assert!(destination.join("base").join("1").exists());
+        let leftovers: Vec<_> = std::fs::read_dir(dir.path())
+            .expect("could not read directory")
+            .collect();
+        assert_eq!(leftovers.len(), 2);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::time::Duration;
+    #[test]
+    fn extensions_are_created_in_new_databases() {
+        let pg = Postgres::build()
+            .extension("pgcrypto")
+            .extension("pg_stat_statements")
+            .start()
+            .expect("could not build postgres database");
+        let superuser = pg.as_superuser();
+        superuser
+            .run_sql("postgres", "CREATE DATABASE with_extensions;")
+            .expect("could not create database");
+
+        let count = superuser
+            .query_scalar(
+                "with_extensions",
+                "SELECT count(*) FROM pg_extension \
+                 WHERE extname IN ('pgcrypto', 'pg_stat_statements')",
+            )
+            .expect("could not query extensions");
+        assert_eq!(count.as_deref(), Some("2"));
+        let preloaded = superuser
+            .query_scalar("postgres", "SHOW shared_preload_libraries")
+            .expect("could not query preloaded libraries");
+        assert_eq!(preloaded.as_deref(), Some("pg_stat_statements"));
+
+        match Postgres::build().extension("no_such_extension").start() {
+            Err(crate::Error::ExtensionUnavailable { name, available }) => {
+                assert_eq!(name, "no_such_extension");
+                assert!(available.iter().any(|extension| extension == "pgcrypto"));
+            }
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
 
-    use url::Url;
+    #[test]
+    fn auth_method_and_pg_hba_lines_are_applied() {
+        let pg = Postgres::build()
+            .auth(AuthMethod::Md5)
+            .pg_hba_line("local all blocked reject")
+            .start()
+            .expect("could not build postgres database");
+        let superuser = pg.as_superuser();
+        superuser
+            .create_user("hashed", "secret")
+            .expect("could not create user");
+        superuser
+            .create_user("blocked", "secret")
+            .expect("could not create user");
+
+        let hash = superuser
+            .query_scalar(
+                "postgres",
+                "SELECT rolpassword FROM pg_authid WHERE rolname = 'hashed'",
+            )
+            .expect("could not query password hash");
+        assert!(hash.expect("password must be set").starts_with("md5"));
+        pg.as_user("hashed", "secret")
+            .run_sql("postgres", "SELECT 1;")
+            .expect("could not connect with password");
+        assert!(pg
+            .as_user("hashed", "wrong")
+            .run_sql("postgres", "SELECT 1;")
+            .is_err());
+        assert!(pg
+            .as_user("blocked", "secret")
+            .run_sql("postgres", "SELECT 1;")
+            .is_err());
 
-    use super::Postgres;
+        let pg = Postgres::build()
+            .auth(AuthMethod::Trust)
+            .start()
+            .expect("could not build postgres database");
+        pg.as_superuser()
+            .create_user("trusted", "secret")
+            .expect("could not create user");
+        pg.as_user("trusted", "wrong")
+            .run_sql("postgres", "SELECT 1;")
+            .expect("could not connect without password");
+    }
 
+    #[cfg(feature = "tls")]
     #[test]
-    fn connection_parameters_decode_socket_urls() {
-        let url =
-            Url::parse("postgres://dev@%2Ftmp%2Fpgdb:5432/dev").expect("socket URL must be valid");
+    fn cert_auth_uses_client_certificates() {
+        let pg = Postgres::build()
+            .auth(AuthMethod::Cert)
+            .start()
+            .expect("could not build postgres database");
+        pg.as_superuser()
+            .create_user("alice", "unused")
+            .expect("could not create user");
 
-        assert_eq!(super::connection_host(&url).as_deref(), Some("/tmp/pgdb"));
-        assert_eq!(super::connection_port(&url), Some(5432));
+        let alice = pg.as_user("alice", "wrong");
+        let url = alice.url("postgres");
+        assert!(url.query_pairs().any(|(key, _)| key == "sslcert"));
+
+        let output = alice
+            .psql("postgres")
+            .args(["-tAc", "SELECT current_user"])
+            .output()
+            .expect("could not run psql");
+        assert!(output.status.success(), "{:?}", output);
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "alice");
+
+        alice
+            .run_sql("postgres", "SELECT 1;")
+            .expect("could not run SQL with client certificate");
+        assert_eq!(
+            alice
+                .query_scalar("postgres", "SELECT current_user")
+                .expect("could not query current user")
+                .as_deref(),
+            Some("alice")
+        );
     }
 
+    #[cfg(feature = "tls")]
     #[test]
-    fn can_change_superuser_pw() {
+    fn tls_connections_are_verified() {
         let pg = Postgres::build()
-            .superuser_pw("helloworld")
+            .tls()
             .start()
             .expect("could not build postgres database");
+        let ca_cert = pg.ca_cert_path().expect("TLS must be enabled");
+        assert!(ca_cert.exists());
+
+        let url = pg.as_superuser().url("postgres");
+        let params: Vec<_> = url.query_pairs().collect();
+        assert!(params.contains(&("sslmode".into(), "verify-full".into())));
+        assert!(params.contains(&("sslrootcert".into(), ca_cert.to_string_lossy())));
+
+        let output = pg
+            .as_superuser()
+            .psql("postgres")
+            .args([
+                "-tAc",
+                "SELECT ssl FROM pg_stat_ssl WHERE pid = pg_backend_pid()",
+            ])
+            .output()
+            .expect("could not run psql");
+        assert!(output.status.success(), "{:?}", output);
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "t");
 
         let su = pg.as_superuser();
-        su.create_user("foo", "bar")
-            .expect("could not create normal user");
+        su.run_sql("postgres", "SELECT 1;")
+            .expect("could not run SQL over TLS");
+        assert_eq!(
+            su.query_scalar(
+                "postgres",
+                "SELECT ssl FROM pg_stat_ssl WHERE pid = pg_backend_pid()"
+            )
+            .expect("could not query TLS status")
+            .as_deref(),
+            Some("t")
+        );
+    }
 
-        // Command executed successfully, check we used the right password.
-        assert_eq!(su.client_url().password(), Some("helloworld"));
+    #[test]
+    fn server_logs_can_be_written_to_file() {
+        let pg = Postgres::build()
+            .log_to_file()
+            .start()
+            .expect("could not build postgres database");
+        assert!(pg
+            .as_superuser()
+            .run_sql("postgres", "SELECT * FROM pgdb_missing_table;")
+            .is_err());
+
+        let log_path = pg.log_path().expect("log must be written to a file");
+        assert!(log_path.starts_with(pg.process.tmp_dir.path()));
+        let recent = pg.recent_logs(5).expect("log must be readable");
+        assert!(recent.lines().count() <= 5);
+        assert!(recent.contains("pgdb_missing_table"));
+
+        let pg = Postgres::build()
+            .start()
+            .expect("could not build postgres database");
+        assert_eq!(pg.log_path(), None);
+        assert_eq!(pg.recent_logs(5), None);
+    }
+
+    #[test]
+    fn keep_on_panic_leaves_server_behind() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let result = std::thread::spawn(move || {
+            let pg = Postgres::build()
+                .keep_on_panic()
+                .start()
+                .expect("could not build postgres database");
+            sender
+                .send((pg.superuser_url().clone(), pg.launcher.data_dir.clone()))
+                .expect("could not send server details");
+            panic!("test failure");
+        })
+        .join();
+        assert!(result.is_err());
+
+        let (superuser_url, data_dir) = receiver.recv().expect("server details must be sent");
+        super::run_psql_command(
+            &super::PgBinaries::new(),
+            &superuser_url,
+            "postgres",
+            "SELECT 1;",
+        )
+        .expect("server must still be running");
+
+        let stopped = std::process::Command::new("pg_ctl")
+            .arg("stop")
+            .arg("--pgdata")
+            .arg(&data_dir)
+            .arg("--mode")
+            .arg("immediate")
+            .stdout(std::process::Stdio::null())
+            .status()
+            .expect("could not run pg_ctl");
+        assert!(stopped.success());
+        let tmp_dir = data_dir
+            .parent()
+            .expect("data dir must be in temporary directory");
+        std::fs::remove_dir_all(tmp_dir).expect("could not remove temporary directory");
     }
 
     #[test]
@@ -788,6 +3006,63 @@ mod tests {
         );
     }
 
+    #[test]
+    fn query_returns_rows_and_scalars() {
+        let pg = Postgres::build()
+            .start()
+            .expect("could not build postgres database");
+        let su = pg.as_superuser();
+
+        let rows = su
+            .query(
+                "postgres",
+                "SELECT * FROM (VALUES (1, 'a'), (2, NULL), (3, '')) AS t (id, name) ORDER BY id",
+            )
+            .expect("could not run query");
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].columns(), ["id", "name"]);
+        assert_eq!(rows[0].get_by_name("name"), Some("a"));
+        assert_eq!(rows[1].get_by_name("name"), None);
+        assert_eq!(rows[2].get_by_name("name"), Some(""));
+
+        assert_eq!(
+            su.query_scalar("postgres", "SELECT 40 + 2")
+                .expect("could not run query"),
+            Some("42".to_string())
+        );
+        assert_eq!(
+            su.query_scalar("postgres", "SELECT 1 WHERE false")
+                .expect("could not run query"),
+            None
+        );
+    }
+
+    #[test]
+    fn query_returns_values_by_name_and_position() {
+        let pg = Postgres::build()
+            .fast()
+            .start()
+            .expect("could not build postgres database");
+        let settings = pg
+            .as_superuser()
+            .query(
+                "postgres",
+                "SELECT current_setting('fsync') AS fsync, current_setting('synchronous_commit'), current_setting('full_page_writes')",
+            )
+            .expect("could not query postgres settings");
+
+        assert_eq!(settings.len(), 1);
+        assert_eq!(settings[0].get_by_name("fsync"), Some("off"));
+        assert_eq!(
+            settings[0].values(),
+            [
+                Some("off".to_string()),
+                Some("off".to_string()),
+                Some("off".to_string())
+            ]
+        );
+    }
+
     #[test]
     fn instances_support_isolated_sockets_and_tcp() {
         let a = Postgres::build()
@@ -866,6 +3141,119 @@ mod tests {
         }
     }
 
+    #[test]
+    fn local_db_cleanup_on_drop() {
+        let keep = crate::db_fixture();
+        let pg = match &keep {
+            crate::DbInstance::Local { _arc, .. } => _arc.clone(),
+            _ => return,
+        };
+
+        let db_url = crate::db_fixture();
+        let db_name = db_url.as_url().path().trim_start_matches('/').to_owned();
+        let db_user = db_url.as_url().username().to_owned();
+        drop(db_url);
+
+        let superuser = pg.as_superuser();
+        let remaining = || {
+            superuser
+                .query_scalar(
+                    "postgres",
+                    &format!(
+                        "SELECT (SELECT count(*) FROM pg_database WHERE datname = '{db_name}') \
+                         + (SELECT count(*) FROM pg_roles WHERE rolname = '{db_user}')"
+                    ),
+                )
+                .expect("could not query catalog")
+        };
+
+        // Cleanup happens in the background.
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while remaining().as_deref() != Some("0") {
+            assert!(
+                Instant::now() < deadline,
+                "fixture database was not dropped"
+            );
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    #[test]
+    fn matrix_failures_are_tagged_with_version() {
+        let mut versions = Vec::new();
+        crate::for_each_pg_version(|version, db| {
+            versions.push(version);
+            let database = db.as_url().path().trim_start_matches('/');
+            crate::run_psql_command(
+                &crate::PgBinaries::new(),
+                db.as_url(),
+                database,
+                "SELECT 1;",
+            )
+            .expect("fixture must be usable");
+        });
+        assert!(!versions.is_empty());
+
+        let result = std::panic::catch_unwind(|| {
+            crate::for_each_pg_version(|_, _| panic!("boom"));
+        });
+        let message = result
+            .expect_err("failure must be reported")
+            .downcast::<String>()
+            .expect("message must be a string");
+        assert!(message.contains(&format!("PostgreSQL {}: boom", versions[0])));
+    }
+
+    #[test]
+    fn template_fixtures_copy_initialized_database() {
+        let init_calls = AtomicUsize::new(0);
+        let init = |url: &Url| {
+            init_calls.fetch_add(1, Ordering::SeqCst);
+            let status = std::process::Command::new("psql")
+                .arg(url.as_str())
+                .args([
+                    "-c",
+                    "CREATE TABLE items (id INT PRIMARY KEY); INSERT INTO items VALUES (1), (2);",
+                ])
+                .status()
+                .expect("could not run psql");
+            assert!(status.success());
+        };
+
+        let db_url = crate::db_fixture_from_template("template_fixtures_copy", init);
+        let db_url2 = crate::db_fixture_from_template("template_fixtures_copy", init);
+        assert_eq!(init_calls.load(Ordering::SeqCst), 1);
+        assert_ne!(db_url.as_str(), db_url2.as_str());
+
+        for db in [&db_url, &db_url2] {
+            // Each fixture user owns its copy and may write to it.
+            let output = std::process::Command::new("psql")
+                .arg(db.as_str())
+                .args([
+                    "-qAtc",
+                    "INSERT INTO items VALUES (3); SELECT count(*) FROM items",
+                ])
+                .output()
+                .expect("could not run psql");
+            assert!(output.status.success());
+            assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), "3");
+        }
+    }
+
+    #[test]
+    fn template_fixtures_recover_from_panicking_init() {
+        let result = std::panic::catch_unwind(|| {
+            crate::db_fixture_from_template("template_fixtures_panic", |_| panic!("init failed"))
+        });
+        assert!(result.is_err());
+
+        let init_calls = AtomicUsize::new(0);
+        let _db_url = crate::db_fixture_from_template("template_fixtures_panic", |_| {
+            init_calls.fetch_add(1, Ordering::SeqCst);
+        });
+        assert_eq!(init_calls.load(Ordering::SeqCst), 1);
+    }
+
     #[test]
     fn external_db_cleanup_on_drop() {
         // Only run this test when external database is configured
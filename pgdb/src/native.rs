@@ -0,0 +1,106 @@
+//! In-process PostgreSQL client.
+//!
+//! Used instead of spawning `psql` when the `native-client` feature is enabled.
+
+use openssl::ssl::{SslConnector, SslFiletype, SslMethod, SslVerifyMode};
+use postgres::config::SslMode;
+use postgres_openssl::MakeTlsConnector;
+use url::Url;
+
+use crate::{row, Error, Row};
+
+/// Connects to `database` on the server given by `url`, using its credentials.
+///
+/// The `sslmode`, `sslrootcert`, `sslcert` and `sslkey` parameters of `url` are honored like
+/// `libpq` does. Without an `sslmode`, no TLS is used.
+pub(crate) fn connect(url: &Url, database: &str) -> Result<postgres::Client, Error> {
+    let host = crate::connection_host(url).expect("URL must have a host");
+    let port = crate::connection_port(url).unwrap_or(5432);
+
+    let mut config = postgres::Config::new();
+    config
+        .host(host.as_ref())
+        .port(port)
+        .user(url.username())
+        .dbname(database);
+    if let Some(password) = url.password() {
+        config.password(password);
+    }
+
+    match tls_connector(url, &mut config)? {
+        Some(connector) => config.connect(connector),
+        None => config.connect(postgres::NoTls),
+    }
+    .map_err(Error::NativeClient)
+}
+
+/// Creates the TLS connector requested by the parameters of `url` and sets the matching SSL mode
+/// on `config`.
+fn tls_connector(
+    url: &Url,
+    config: &mut postgres::Config,
+) -> Result<Option<MakeTlsConnector>, Error> {
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+
+    let Some(sslmode) = param("sslmode") else {
+        return Ok(None);
+    };
+    let root_cert = param("sslrootcert");
+    // Like `libpq`, `require` verifies the CA if a root certificate is given.
+    let (ssl_mode, verify_ca, verify_hostname) = match sslmode.as_str() {
+        "disable" => return Ok(None),
+        "allow" | "prefer" => (SslMode::Prefer, false, false),
+        "require" => (SslMode::Require, root_cert.is_some(), false),
+        "verify-ca" => (SslMode::Require, true, false),
+        "verify-full" => (SslMode::Require, true, true),
+        _ => return Err(Error::UnsupportedSslMode(sslmode)),
+    };
+    config.ssl_mode(ssl_mode);
+
+    let mut builder = SslConnector::builder(SslMethod::tls()).map_err(Error::NativeClientTls)?;
+    if verify_ca {
+        if let Some(root_cert) = &root_cert {
+            builder
+                .set_ca_file(root_cert)
+                .map_err(Error::NativeClientTls)?;
+        }
+    } else {
+        builder.set_verify(SslVerifyMode::NONE);
+    }
+    if let Some(cert) = param("sslcert") {
+        builder
+            .set_certificate_chain_file(cert)
+            .map_err(Error::NativeClientTls)?;
+    }
+    if let Some(key) = param("sslkey") {
+        builder
+            .set_private_key_file(key, SslFiletype::PEM)
+            .map_err(Error::NativeClientTls)?;
+    }
+
+    let mut connector = MakeTlsConnector::new(builder.build());
+    connector.set_callback(move |connect_config, _| {
+        connect_config.set_verify_hostname(verify_hostname);
+        Ok(())
+    });
+    Ok(Some(connector))
+}
+
+/// Executes one or more SQL statements separated by semicolons.
+pub(crate) fn batch_execute(url: &Url, database: &str, sql: &str) -> Result<(), Error> {
+    connect(url, database)?
+        .batch_execute(sql)
+        .map_err(Error::NativeClient)
+}
+
+/// Runs a query using the simple query protocol, returning all values in text format.
+pub(crate) fn simple_query(url: &Url, database: &str, sql: &str) -> Result<Vec<Row>, Error> {
+    let messages = connect(url, database)?
+        .simple_query(sql)
+        .map_err(Error::NativeClient)?;
+    Ok(row::from_simple_query(messages))
+}
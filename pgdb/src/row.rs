@@ -0,0 +1,185 @@
+//! Query results.
+//!
+//! Rows returned by [`PostgresClient::query`](crate::PostgresClient::query) contain the text
+//! representation of every column, exactly as PostgreSQL formats it.
+
+use std::sync::Arc;
+
+/// A single row of a query result.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Row {
+    /// Names of all columns, shared by all rows of a result.
+    columns: Arc<[String]>,
+    /// Column values in text format, `None` for `NULL`.
+    values: Vec<Option<String>>,
+}
+
+impl Row {
+    /// Returns the names of all columns.
+    #[inline]
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    /// Returns all column values, `None` for `NULL`.
+    #[inline]
+    pub fn values(&self) -> &[Option<String>] {
+        &self.values
+    }
+
+    /// Returns the value of the column at `index`.
+    ///
+    /// Returns `None` if the value is `NULL` or the column does not exist.
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.values.get(index).and_then(Option::as_deref)
+    }
+
+    /// Returns the value of the first column named `column`.
+    ///
+    /// Returns `None` if the value is `NULL` or the column does not exist.
+    pub fn get_by_name(&self, column: &str) -> Option<&str> {
+        let index = self.columns.iter().position(|name| name == column)?;
+        self.get(index)
+    }
+}
+
+/// Parses `psql --csv` output into rows.
+///
+/// Unquoted fields equal to `null_marker` are interpreted as `NULL`. Returns `None` if the output
+/// is not well-formed CSV.
+#[cfg_attr(feature = "native-client", allow(dead_code))]
+pub(crate) fn parse_csv(output: &str, null_marker: &str) -> Option<Vec<Row>> {
+    let mut records = parse_csv_records(output)?.into_iter();
+
+    let columns: Arc<[String]> = match records.next() {
+        Some(header) => header.into_iter().map(|(name, _)| name).collect(),
+        // Statements that do not return rows produce no output at all.
+        None => return Some(Vec::new()),
+    };
+
+    records
+        .map(|record| {
+            if record.len() != columns.len() {
+                return None;
+            }
+            let values = record
+                .into_iter()
+                .map(|(value, quoted)| {
+                    if !quoted && value == null_marker {
+                        None
+                    } else {
+                        Some(value)
+                    }
+                })
+                .collect();
+            Some(Row {
+                columns: columns.clone(),
+                values,
+            })
+        })
+        .collect()
+}
+
+/// Builds rows from the native client's simple query results.
+#[cfg(feature = "native-client")]
+pub(crate) fn from_simple_query(messages: Vec<postgres::SimpleQueryMessage>) -> Vec<Row> {
+    let mut columns: Option<Arc<[String]>> = None;
+    let mut rows = Vec::new();
+
+    for message in messages {
+        match message {
+            postgres::SimpleQueryMessage::RowDescription(description) => {
+                columns = Some(
+                    description
+                        .iter()
+                        .map(|column| column.name().to_string())
+                        .collect(),
+                );
+            }
+            postgres::SimpleQueryMessage::Row(row) => {
+                let columns = columns
+                    .get_or_insert_with(|| {
+                        row.columns()
+                            .iter()
+                            .map(|column| column.name().to_string())
+                            .collect()
+                    })
+                    .clone();
+                let values = (0..row.len())
+                    .map(|index| row.get(index).map(str::to_string))
+                    .collect();
+                rows.push(Row { columns, values });
+            }
+            _ => {}
+        }
+    }
+
+    rows
+}
+
+/// Splits CSV into records of fields, remembering whether each field was quoted.
+fn parse_csv_records(input: &str) -> Option<Vec<Vec<(String, bool)>>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while chars.peek().is_some() {
+        let mut field = String::new();
+        let quoted = chars.peek() == Some(&'"');
+
+        if quoted {
+            chars.next();
+            loop {
+                match chars.next()? {
+                    '"' if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    }
+                    '"' => break,
+                    c => field.push(c),
+                }
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c == ',' || c == '\n' || c == '\r' {
+                    break;
+                }
+                field.push(c);
+                chars.next();
+            }
+        }
+        record.push((field, quoted));
+
+        match chars.next() {
+            Some(',') => {}
+            Some('\r') if chars.peek() == Some(&'\n') => {
+                chars.next();
+                records.push(std::mem::take(&mut record));
+            }
+            Some('\n') | None => records.push(std::mem::take(&mut record)),
+            Some(_) => return None,
+        }
+    }
+
+    Some(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_csv;
+
+    #[test]
+    fn parses_psql_csv_output() {
+        let rows = parse_csv("a,b,c,d e\nNULL,,\"x,\"\"y\",\"multi\nline\"\n", "NULL")
+            .expect("CSV must be valid");
+
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].columns(), ["a", "b", "c", "d e"]);
+        assert_eq!(rows[0].get(0), None);
+        assert_eq!(rows[0].get(1), Some(""));
+        assert_eq!(rows[0].get_by_name("c"), Some("x,\"y"));
+        assert_eq!(rows[0].get_by_name("d e"), Some("multi\nline"));
+        assert_eq!(parse_csv("", "NULL"), Some(Vec::new()));
+        assert_eq!(parse_csv("a\n\"unterminated\n", "NULL"), None);
+    }
+}
@@ -0,0 +1,351 @@
+//! Servers shared across processes.
+//!
+//! Test runners like `cargo nextest` run every test in its own process, so the per-process server
+//! of [`db_fixture`](crate::db_fixture) would be started once per test. Instead, the first process
+//! starts a server and publishes it in a state file inside a shared directory. Every attached
+//! process holds a shared lock on the `refs` file; the last one to detach stops the server.
+//!
+//! All changes to the state file happen while holding an exclusive lock on the `lock` file.
+
+use std::{
+    fs::{self, DirBuilder, File, OpenOptions},
+    io::{self, Write},
+    os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt},
+    path, process, thread,
+};
+
+use url::Url;
+
+use crate::{Error, PgBinaries, PostgresBuilder};
+
+/// Name of the file serializing attaching and detaching processes.
+const LOCK_FILE: &str = "lock";
+
+/// Name of the file locked in shared mode by every attached process.
+const REFS_FILE: &str = "refs";
+
+/// Name of the file describing the running server.
+const STATE_FILE: &str = "server";
+
+/// A server shared with other processes.
+///
+/// Created by fixture functions when `PGDB_SHARED` or `PGDB_SHARED_DIR` is set. The server is
+/// stopped once the last [`SharedServer`] in any process is dropped. With
+/// [`PostgresBuilder::keep_on_panic`], one dropped during a panic instead leaves the server running
+/// and unpublishes it, so no other process stops it.
+#[derive(Debug)]
+pub struct SharedServer {
+    /// Directory holding the lock and state files.
+    dir: path::PathBuf,
+    /// Open `refs` file, locked in shared mode while attached.
+    refs: File,
+    /// Superuser URL of the server.
+    superuser_url: Url,
+    /// Binaries of this process, used to create and drop databases and to stop the server.
+    binaries: PgBinaries,
+    /// Whether to leave the server running when detaching during a panic.
+    keep_on_panic: bool,
+}
+
+/// Everything needed to connect to and stop a server not owned by any [`Postgres`](crate::Postgres).
+///
+/// The state file is written by another process, so it must never name binaries to execute.
+#[derive(Debug)]
+pub(crate) struct ServerState {
+    /// URL for the instance with superuser credentials.
+    pub(crate) superuser_url: Url,
+    /// Data directory of the server.
+    pub(crate) data_dir: path::PathBuf,
+    /// Directory holding all temporary data, removed after stopping the server.
+    pub(crate) tmp_dir: path::PathBuf,
+    /// `pg_ctl` shutdown mode.
+    pub(crate) shutdown_mode: &'static str,
+}
+
+impl SharedServer {
+    /// Attaches to the server published in `dir`, starting one with `builder` if there is none.
+    pub(crate) fn attach(dir: &path::Path, builder: &PostgresBuilder) -> Result<Self, Error> {
+        create_private_dir(dir)?;
+        let lock = lock_dir(dir)?;
+        let pg_ctl_binary = builder.binaries.pg_ctl_or_default();
+        let refs = open_file(&dir.join(REFS_FILE))?;
+
+        let state_file = dir.join(STATE_FILE);
+        let mut state = match fs::read_to_string(&state_file) {
+            Ok(content) => Some(ServerState::parse(&content).map_err(Error::SharedServer)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(Error::SharedServer(err)),
+        };
+
+        if state.is_some() && refs.try_lock().is_ok() {
+            // All processes using the server exited without detaching.
+            if let Some(stale) = state.take() {
+                stale.stop(&pg_ctl_binary);
+            }
+            fs::remove_file(&state_file).map_err(Error::SharedServer)?;
+            refs.unlock().map_err(Error::SharedServer)?;
+        }
+
+        let state = match state {
+            Some(state) => state,
+            None => {
+                let state = builder.start()?.detach();
+                let staging_file = dir.join(format!("{}.tmp", STATE_FILE));
+                write_private_file(&staging_file, &state.to_string())
+                    .and_then(|()| fs::rename(&staging_file, &state_file))
+                    .map_err(|err| {
+                        state.stop(&pg_ctl_binary);
+                        Error::SharedServer(err)
+                    })?;
+                state
+            }
+        };
+
+        refs.lock_shared().map_err(Error::SharedServer)?;
+        drop(lock);
+
+        Ok(SharedServer {
+            dir: dir.to_owned(),
+            refs,
+            superuser_url: state.superuser_url,
+            binaries: builder.binaries.clone(),
+            keep_on_panic: builder.keep_on_panic,
+        })
+    }
+
+    /// Returns the superuser URL of the shared server.
+    pub fn superuser_url(&self) -> &Url {
+        &self.superuser_url
+    }
+
+    /// Returns the binaries used by this process to access the shared server.
+    pub(crate) fn binaries(&self) -> &PgBinaries {
+        &self.binaries
+    }
+
+    /// Releases this process' reference, stopping the server if it was the last one.
+    fn detach(&self) -> Result<(), Error> {
+        let _lock = lock_dir(&self.dir)?;
+        self.refs.unlock().map_err(Error::SharedServer)?;
+        let state_file = self.dir.join(STATE_FILE);
+
+        if self.keep_on_panic && thread::panicking() {
+            // Processes attaching later start a new server, the ones still attached keep using
+            // this one.
+            let content = fs::read_to_string(&state_file).map_err(Error::SharedServer)?;
+            let state = ServerState::parse(&content).map_err(Error::SharedServer)?;
+            fs::remove_file(&state_file).map_err(Error::SharedServer)?;
+
+            eprintln!("pgdb: keeping shared PostgreSQL server after panic");
+            eprintln!("  data dir: {}", state.data_dir.display());
+            eprintln!("  url:      {}", state.superuser_url);
+            return Ok(());
+        }
+
+        if self.refs.try_lock().is_err() {
+            // Other processes are still attached.
+            return Ok(());
+        }
+
+        let content = fs::read_to_string(&state_file).map_err(Error::SharedServer)?;
+        ServerState::parse(&content)
+            .map_err(Error::SharedServer)?
+            .stop(&self.binaries.pg_ctl_or_default());
+        fs::remove_file(&state_file).map_err(Error::SharedServer)
+    }
+}
+
+impl Drop for SharedServer {
+    fn drop(&mut self) {
+        // Best effort: a server left behind is stopped by the next process attaching.
+        let _ = self.detach();
+    }
+}
+
+impl ServerState {
+    /// Parses the contents of a state file.
+    fn parse(content: &str) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid shared server state");
+
+        let mut lines = content.lines();
+        let mut next = || lines.next().ok_or_else(invalid);
+
+        let superuser_url = Url::parse(next()?).map_err(|_| invalid())?;
+        let data_dir = next()?.into();
+        let tmp_dir = next()?.into();
+        let shutdown_mode = match next()? {
+            "fast" => "fast",
+            "immediate" => "immediate",
+            _ => return Err(invalid()),
+        };
+
+        Ok(ServerState {
+            superuser_url,
+            data_dir,
+            tmp_dir,
+            shutdown_mode,
+        })
+    }
+
+    /// Stops the server through `pg_ctl_binary` and removes its temporary directory, ignoring all
+    /// errors.
+    pub(crate) fn stop(&self, pg_ctl_binary: &path::Path) {
+        let _ = process::Command::new(pg_ctl_binary)
+            .arg("stop")
+            .arg("--pgdata")
+            .arg(&self.data_dir)
+            .arg("--mode")
+            .arg(self.shutdown_mode)
+            .arg("--wait")
+            .stdout(process::Stdio::null())
+            .stderr(process::Stdio::null())
+            .status();
+        let _ = fs::remove_dir_all(&self.tmp_dir);
+    }
+}
+
+impl std::fmt::Display for ServerState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}", self.superuser_url)?;
+        writeln!(f, "{}", self.data_dir.display())?;
+        writeln!(f, "{}", self.tmp_dir.display())?;
+        writeln!(f, "{}", self.shutdown_mode)
+    }
+}
+
+/// Creates `dir` accessible only to the current user, refusing existing directories owned by
+/// someone else.
+fn create_private_dir(dir: &path::Path) -> Result<(), Error> {
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .map_err(Error::SharedServer)?;
+
+    let metadata = fs::symlink_metadata(dir).map_err(Error::SharedServer)?;
+    // SAFETY: `geteuid` cannot fail and has no preconditions.
+    if !metadata.is_dir() || metadata.uid() != unsafe { libc::geteuid() } {
+        return Err(Error::UntrustedSharedDir(dir.to_owned()));
+    }
+    Ok(())
+}
+
+/// Writes `content` to a new file at `path` that is only readable by the current user.
+///
+/// The state file contains the superuser password.
+fn write_private_file(path: &path::Path, content: &str) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .and_then(|mut file| file.write_all(content.as_bytes()))
+}
+
+/// Opens `path` for locking, creating it if necessary.
+fn open_file(path: &path::Path) -> Result<File, Error> {
+    OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .map_err(Error::SharedServer)
+}
+
+/// Takes the exclusive lock on `dir`, released when the returned file is dropped.
+fn lock_dir(dir: &path::Path) -> Result<File, Error> {
+    let lock = open_file(&dir.join(LOCK_FILE))?;
+    lock.lock().map_err(Error::SharedServer)?;
+    Ok(lock)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::MetadataExt;
+
+    use super::{SharedServer, STATE_FILE};
+    use crate::Postgres;
+
+    #[test]
+    fn last_detaching_process_stops_server() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let builder = Postgres::build();
+
+        // Separately opened lock files behave like separate processes.
+        let first = SharedServer::attach(dir.path(), &builder).expect("could not attach");
+        let second = SharedServer::attach(dir.path(), &builder).expect("could not attach again");
+        assert_eq!(first.superuser_url(), second.superuser_url());
+        let socket_dir = crate::connection_host(first.superuser_url())
+            .expect("URL must have a host")
+            .into_owned();
+
+        drop(first);
+        crate::run_psql_command(
+            second.binaries(),
+            second.superuser_url(),
+            "postgres",
+            "SELECT 1;",
+        )
+        .expect("server must still be running");
+        let mode = std::fs::metadata(dir.path().join(STATE_FILE))
+            .expect("state file must exist")
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        drop(second);
+        assert!(!dir.path().join(STATE_FILE).exists());
+        assert!(!std::path::Path::new(&socket_dir).exists());
+    }
+
+    #[test]
+    fn keep_on_panic_unpublishes_server() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let mut builder = Postgres::build();
+        builder.keep_on_panic();
+
+        let shared_dir = dir.path().to_owned();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let result = std::thread::spawn(move || {
+            let server = SharedServer::attach(&shared_dir, &builder).expect("could not attach");
+            sender
+                .send(server.superuser_url().clone())
+                .expect("could not send server details");
+            panic!("test failure");
+        })
+        .join();
+        assert!(result.is_err());
+
+        let superuser_url = receiver.recv().expect("server details must be sent");
+        assert!(!dir.path().join(STATE_FILE).exists());
+        crate::run_psql_command(
+            &crate::PgBinaries::new(),
+            &superuser_url,
+            "postgres",
+            "SELECT 1;",
+        )
+        .expect("server must still be running");
+
+        let data_dir = std::path::Path::new(
+            crate::connection_host(&superuser_url)
+                .expect("URL must have a host")
+                .as_ref(),
+        )
+        .join("db");
+        let stopped = std::process::Command::new("pg_ctl")
+            .arg("stop")
+            .arg("--pgdata")
+            .arg(&data_dir)
+            .arg("--mode")
+            .arg("immediate")
+            .stdout(std::process::Stdio::null())
+            .status()
+            .expect("could not run pg_ctl");
+        assert!(stopped.success());
+        std::fs::remove_dir_all(data_dir.parent().expect("data dir must have a parent"))
+            .expect("could not remove temporary directory");
+    }
+}
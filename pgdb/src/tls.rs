@@ -0,0 +1,152 @@
+//! Throwaway certificates for TLS-enabled servers.
+//!
+//! Every server gets its own CA, which is only used to sign the server's and clients' certificates
+//! and discarded along with the temporary directory.
+
+use std::{
+    fs, io,
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+
+use hex_fmt::HexFmt;
+use rcgen::{
+    BasicConstraints, CertificateParams, CertifiedIssuer, DnType, ExtendedKeyUsagePurpose, IsCa,
+    Issuer, KeyPair, KeyUsagePurpose,
+};
+
+use crate::Error;
+
+/// Name of the directory holding the certificates inside the temporary directory.
+const TLS_DIR: &str = "tls";
+
+/// Certificate and key files of a TLS-enabled server.
+#[derive(Debug)]
+pub(crate) struct TlsFiles {
+    /// Directory holding all files.
+    dir: PathBuf,
+    /// Certificate of the CA that signed the server certificate.
+    pub(crate) ca_cert: PathBuf,
+    /// Private key of the CA, used to sign client certificates.
+    ca_key: PathBuf,
+    /// Server certificate.
+    pub(crate) server_cert: PathBuf,
+    /// Private key of the server certificate.
+    pub(crate) server_key: PathBuf,
+}
+
+impl TlsFiles {
+    /// Returns the locations of the files inside the temporary directory `tmp_dir`.
+    pub(crate) fn new(tmp_dir: &Path) -> Self {
+        let dir = tmp_dir.join(TLS_DIR);
+        TlsFiles {
+            ca_cert: dir.join("ca.crt"),
+            ca_key: dir.join("ca.key"),
+            server_cert: dir.join("server.crt"),
+            server_key: dir.join("server.key"),
+            dir,
+        }
+    }
+
+    /// Generates a CA and a server certificate valid for `hosts`, signed by the CA.
+    pub(crate) fn generate(&self, hosts: Vec<String>) -> Result<(), Error> {
+        let ca_key = KeyPair::generate().map_err(Error::GenerateCertificate)?;
+        let ca = CertifiedIssuer::self_signed(ca_params()?, ca_key)
+            .map_err(Error::GenerateCertificate)?;
+
+        let server_key = KeyPair::generate().map_err(Error::GenerateCertificate)?;
+        let mut server_params =
+            CertificateParams::new(hosts).map_err(Error::GenerateCertificate)?;
+        server_params
+            .distinguished_name
+            .push(DnType::CommonName, "pgdb test server");
+        server_params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+        let server_cert = server_params
+            .signed_by(&server_key, &ca)
+            .map_err(Error::GenerateCertificate)?;
+
+        fs::create_dir_all(&self.dir).map_err(Error::CertificateFiles)?;
+        fs::write(&self.ca_cert, ca.pem()).map_err(Error::CertificateFiles)?;
+        write_key(&self.ca_key, &ca.key().serialize_pem()).map_err(Error::CertificateFiles)?;
+        fs::write(&self.server_cert, server_cert.pem()).map_err(Error::CertificateFiles)?;
+        write_key(&self.server_key, &server_key.serialize_pem()).map_err(Error::CertificateFiles)
+    }
+
+    /// Returns the certificate and key for authenticating as `username`, generating them on first
+    /// use.
+    pub(crate) fn client_cert(&self, username: &str) -> Result<(PathBuf, PathBuf), Error> {
+        let client_dir = self.dir.join(format!("client-{}", HexFmt(username)));
+        let cert = client_dir.join("client.crt");
+        let key = client_dir.join("client.key");
+        if client_dir.is_dir() {
+            return Ok((cert, key));
+        }
+
+        let ca_key_pem = fs::read_to_string(&self.ca_key).map_err(Error::CertificateFiles)?;
+        let ca_key = KeyPair::from_pem(&ca_key_pem).map_err(Error::GenerateCertificate)?;
+        let ca = Issuer::new(ca_params()?, ca_key);
+
+        let client_key = KeyPair::generate().map_err(Error::GenerateCertificate)?;
+        let mut client_params =
+            CertificateParams::new(Vec::new()).map_err(Error::GenerateCertificate)?;
+        client_params
+            .distinguished_name
+            .push(DnType::CommonName, username);
+        client_params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+        let client_cert = client_params
+            .signed_by(&client_key, &ca)
+            .map_err(Error::GenerateCertificate)?;
+
+        // Files are moved into place together, so concurrent callers never see a certificate
+        // without its matching key.
+        let staging_dir = tempfile::tempdir_in(&self.dir).map_err(Error::CertificateFiles)?;
+        fs::write(staging_dir.path().join("client.crt"), client_cert.pem())
+            .map_err(Error::CertificateFiles)?;
+        write_key(
+            &staging_dir.path().join("client.key"),
+            &client_key.serialize_pem(),
+        )
+        .map_err(Error::CertificateFiles)?;
+        match fs::rename(staging_dir.path(), &client_dir) {
+            // The directory is gone, so it must not be removed on drop.
+            Ok(()) => {
+                let _ = staging_dir.keep();
+            }
+            // Another caller generated the certificate first.
+            Err(_) if client_dir.is_dir() => {}
+            Err(err) => return Err(Error::CertificateFiles(err)),
+        }
+
+        Ok((cert, key))
+    }
+}
+
+/// Returns the parameters of the CA certificate.
+///
+/// They are identical for every CA, so the CA can be restored from its key alone.
+fn ca_params() -> Result<CertificateParams, Error> {
+    let mut params = CertificateParams::new(Vec::new()).map_err(Error::GenerateCertificate)?;
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params
+        .distinguished_name
+        .push(DnType::CommonName, "pgdb test CA");
+    params.key_usages = vec![
+        KeyUsagePurpose::KeyCertSign,
+        KeyUsagePurpose::CrlSign,
+        KeyUsagePurpose::DigitalSignature,
+    ];
+    Ok(params)
+}
+
+/// Writes a private key that is only readable by the current user.
+///
+/// Both PostgreSQL and libpq refuse to use keys readable by other users.
+fn write_key(path: &Path, pem: &str) -> io::Result<()> {
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .and_then(|mut file| file.write_all(pem.as_bytes()))
+}
@@ -1,15 +1,26 @@
 #![doc = include_str!("../README.md")]
 
 use std::{
+    collections::BTreeMap,
     env,
-    ffi::OsString,
-    os::unix::process::ExitStatusExt,
+    ffi::{OsStr, OsString},
+    fs,
+    io::{self, Write},
+    os::unix::{
+        ffi::OsStrExt,
+        io::{FromRawFd, RawFd},
+        net::UnixDatagram,
+        process::ExitStatusExt,
+    },
+    path::{Path, PathBuf},
     process::{self, ExitStatus},
     thread,
     time::Duration,
 };
 
-use clap::Parser;
+use anyhow::Context;
+use clap::{Parser, ValueEnum};
+use serde::Serialize;
 use signal_hook::{
     consts::{SIGHUP, SIGINT, SIGTERM},
     iterator::Signals,
@@ -27,6 +38,67 @@ fn parse_postgres_option(value: &str) -> Result<(String, String), String> {
     Ok((name.to_string(), value.to_string()))
 }
 
+/// An additional database in `NAME[:USER[:PASSWORD]]` form.
+#[derive(Clone, Debug, PartialEq)]
+struct DatabaseSpec {
+    /// Name of the database.
+    name: String,
+    /// User owning the database, defaults to the database name.
+    user: String,
+    /// Password of the owner, defaults to the user name.
+    password: String,
+}
+
+impl DatabaseSpec {
+    /// Returns the name of the environment variable holding the database URL.
+    fn url_var(&self) -> String {
+        let suffix: String = self
+            .name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        format!("DATABASE_URL_{suffix}")
+    }
+}
+
+/// Parses an additional database in `NAME[:USER[:PASSWORD]]` form.
+fn parse_database_spec(value: &str) -> Result<DatabaseSpec, String> {
+    let mut parts = value.splitn(3, ':');
+    let name = parts.next().unwrap_or_default();
+    if name.is_empty() {
+        return Err("database name must not be empty".to_string());
+    }
+    let user = parts.next().unwrap_or(name);
+    if user.is_empty() {
+        return Err("user name must not be empty".to_string());
+    }
+    let password = parts.next().unwrap_or(user);
+    Ok(DatabaseSpec {
+        name: name.to_string(),
+        user: user.to_string(),
+        password: password.to_string(),
+    })
+}
+
+/// Format of the connection details printed once the server is ready.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum OutputFormat {
+    /// Human-readable description.
+    Text,
+    /// `NAME=VALUE` lines, e.g. for `.env` files.
+    Env,
+    /// A JSON object.
+    Json,
+    /// `export NAME='VALUE'` lines to be evaluated by a POSIX shell.
+    Shell,
+}
+
 /// Create a temporary postgres database with one user owning a single DB.
 #[derive(Debug, Parser)]
 #[command(name = "pgdb", version, trailing_var_arg = true)]
@@ -37,6 +109,9 @@ struct Opts {
     /// TCP port to use; implies --tcp.
     #[arg(short, long)]
     port: Option<u16>,
+    /// Enable TLS with a generated throwaway certificate; implies --tcp.
+    #[arg(long)]
+    tls: bool,
     /// Username for regular database user.
     #[arg(short, long, env = "PGDB_USER", default_value = "dev")]
     user: String,
@@ -52,6 +127,18 @@ struct Opts {
     /// Name of regular user-owned database.
     #[arg(short, long, env = "PGDB_DB", default_value = "dev")]
     db: String,
+    /// Additional database with its own owner, exported as DATABASE_URL_<NAME>.
+    #[arg(long = "database", value_name = "NAME[:USER[:PASSWORD]]", value_parser = parse_database_spec)]
+    databases: Vec<DatabaseSpec>,
+    /// SQL file to load into the regular user's database, after migrations.
+    #[arg(long, value_name = "FILE")]
+    init_sql: Vec<PathBuf>,
+    /// Directory of `.sql` migrations to load into the regular user's database, in file name order.
+    #[arg(long, value_name = "DIR")]
+    migrations: Option<PathBuf>,
+    /// Keep the cluster in this directory and reuse it on later runs.
+    #[arg(short = 'D', long, env = "PGDB_DATA_DIR", value_name = "PATH")]
+    data_dir: Option<PathBuf>,
     /// Password for the superuser ("postgres") account, default is to generate randomly.
     #[arg(short = 'S', long)]
     superuser_pw: Option<String>,
@@ -64,6 +151,36 @@ struct Opts {
     /// Maximum time in seconds to wait for forceful PostgreSQL shutdown.
     #[arg(long, value_name = "SECONDS")]
     force_shutdown_timeout: Option<u64>,
+    /// Extension to create in every database, e.g. pgcrypto or postgis.
+    #[arg(long = "extension", value_name = "NAME")]
+    extensions: Vec<String>,
+    /// Required PostgreSQL version, e.g. 16 or ">=15"; picks the newest matching installation.
+    #[arg(long, value_name = "REQ")]
+    pg_version: Option<String>,
+    /// Locale of a new cluster, e.g. en_US.UTF-8; default is C.
+    #[arg(long, value_name = "LOCALE")]
+    locale: Option<String>,
+    /// Collation order of a new cluster, overriding --locale.
+    #[arg(long, value_name = "LOCALE")]
+    lc_collate: Option<String>,
+    /// Character classification of a new cluster, overriding --locale.
+    #[arg(long, value_name = "LOCALE")]
+    lc_ctype: Option<String>,
+    /// Default encoding of a new cluster; default is UTF8.
+    #[arg(long, value_name = "ENCODING")]
+    encoding: Option<String>,
+    /// Use ICU with this locale, e.g. en-US, as the default collation provider.
+    #[arg(long, value_name = "LOCALE")]
+    icu_locale: Option<String>,
+    /// Enable data checksums in a new cluster.
+    #[arg(long)]
+    data_checksums: bool,
+    /// Additional argument for initdb, e.g. --initdb-arg=--wal-segsize=64.
+    #[arg(long, value_name = "ARG", allow_hyphen_values = true)]
+    initdb_arg: Vec<OsString>,
+    /// Configuration file to include from postgresql.conf.
+    #[arg(long, value_name = "FILE")]
+    config_file: Vec<PathBuf>,
     /// PostgreSQL server option in NAME=VALUE form.
     #[arg(long, value_name = "NAME=VALUE", value_parser = parse_postgres_option)]
     postgres_option: Vec<(String, String)>,
@@ -79,6 +196,18 @@ struct Opts {
     /// Export the PostgreSQL superuser URL as PGDB_TESTS_URL.
     #[arg(short = 'E', long)]
     export_tests_url: bool,
+    /// Format of the connection details printed in interactive mode.
+    #[arg(long, value_enum, default_value = "text")]
+    output: OutputFormat,
+    /// Atomically write the connection details to this file once ready, in --output format.
+    #[arg(long, value_name = "PATH")]
+    output_file: Option<PathBuf>,
+    /// Create this file once the server is ready, removing it on exit.
+    #[arg(long, value_name = "PATH")]
+    ready_file: Option<PathBuf>,
+    /// Write a newline to this inherited file descriptor and close it once the server is ready.
+    #[arg(long, value_name = "N")]
+    ready_fd: Option<RawFd>,
     /// Command to run with the temporary database.
     #[arg(name = "command")]
     command: Vec<OsString>,
@@ -94,13 +223,179 @@ impl Opts {
     fn export_tests_url(&self) -> bool {
         self.export_tests_url || self.test
     }
+
+    /// Checks that all databases and their owners are distinct.
+    fn check_databases(&self) -> anyhow::Result<()> {
+        let mut names = vec![self.db.as_str()];
+        let mut users = vec![self.user.as_str()];
+        for spec in &self.databases {
+            if names.contains(&spec.name.as_str()) {
+                anyhow::bail!("database `{}` is specified more than once", spec.name);
+            }
+            if users.contains(&spec.user.as_str()) {
+                anyhow::bail!("user `{}` must not own more than one database", spec.user);
+            }
+            names.push(&spec.name);
+            users.push(&spec.user);
+        }
+        Ok(())
+    }
+
+    /// Returns all SQL files to load, migrations first.
+    fn sql_files(&self) -> anyhow::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        if let Some(migrations) = &self.migrations {
+            let read_dir = fs::read_dir(migrations).with_context(|| {
+                format!("could not read migrations from {}", migrations.display())
+            })?;
+            for entry in read_dir {
+                let path = entry?.path();
+                if path.is_file() && path.extension().is_some_and(|ext| ext == "sql") {
+                    files.push(path);
+                }
+            }
+            files.sort();
+        }
+        files.extend(self.init_sql.iter().cloned());
+        Ok(files)
+    }
+}
+
+/// Loads SQL files in order, stopping at the first failure.
+fn load_sql_files(
+    files: &[PathBuf],
+    mut load: impl FnMut(&Path) -> Result<(), pgdb::Error>,
+) -> anyhow::Result<()> {
+    for file in files {
+        load(file).with_context(|| format!("failed to load {}", file.display()))?;
+    }
+    Ok(())
+}
+
+/// Connection URLs of all databases created for a run.
+struct Setup {
+    /// URL with superuser credentials.
+    superuser_url: Url,
+    /// URL of the regular user's database.
+    user_url: Url,
+    /// URLs of the additional databases.
+    database_urls: Vec<(DatabaseSpec, Url)>,
+    /// Whether the databases live on an external server.
+    external: bool,
+    /// Process ID of the local server.
+    pid: Option<u32>,
+}
+
+/// Connection details in the `json` output format.
+#[derive(Debug, Serialize)]
+struct JsonOutput<'a> {
+    /// URL with superuser credentials.
+    superuser_url: &'a str,
+    /// URL of the regular user's database.
+    user_url: &'a str,
+    /// Host name or Unix socket directory.
+    host: &'a str,
+    /// Port of the server.
+    port: u16,
+    /// Unix socket directory, if connecting through a socket.
+    socket_dir: Option<&'a str>,
+    /// Process ID of the local server.
+    pid: Option<u32>,
+    /// URLs of the additional databases by name.
+    databases: BTreeMap<&'a str, &'a str>,
+}
+
+impl Setup {
+    /// Returns the environment variables describing the regular user's connection.
+    fn variables(&self, opts: &Opts) -> Vec<(String, String)> {
+        let host = pgdb::connection_host(&self.user_url).expect("URL must have a host");
+        let port = pgdb::connection_port(&self.user_url).unwrap_or(5432);
+
+        let mut variables = vec![
+            ("DATABASE_URL".to_string(), self.user_url.to_string()),
+            ("PGHOST".to_string(), host.into_owned()),
+            ("PGPORT".to_string(), port.to_string()),
+            ("PGUSER".to_string(), opts.user.clone()),
+            ("PGPASSWORD".to_string(), opts.password.clone()),
+            ("PGDATABASE".to_string(), opts.db.clone()),
+        ];
+        for (key, value) in self.user_url.query_pairs() {
+            match key.as_ref() {
+                "sslmode" => variables.push(("PGSSLMODE".to_string(), value.into_owned())),
+                "sslrootcert" => variables.push(("PGSSLROOTCERT".to_string(), value.into_owned())),
+                _ => {}
+            }
+        }
+        for (spec, url) in &self.database_urls {
+            variables.push((spec.url_var(), url.to_string()));
+        }
+        variables
+    }
+
+    /// Renders the connection details in the given format.
+    fn render(&self, opts: &Opts, format: OutputFormat) -> String {
+        let mut variables = self.variables(opts);
+        variables.push((
+            "PGDB_SUPERUSER_URL".to_string(),
+            self.superuser_url.to_string(),
+        ));
+
+        match format {
+            OutputFormat::Text => render_text(opts, self),
+            OutputFormat::Env => variables
+                .iter()
+                .map(|(name, value)| format!("{name}={value}\n"))
+                .collect(),
+            OutputFormat::Shell => variables
+                .iter()
+                .map(|(name, value)| format!("export {name}='{}'\n", value.replace('\'', "'\\''")))
+                .collect(),
+            OutputFormat::Json => {
+                let host =
+                    pgdb::connection_host(&self.superuser_url).expect("URL must have a host");
+                let output = JsonOutput {
+                    superuser_url: self.superuser_url.as_str(),
+                    user_url: self.user_url.as_str(),
+                    host: &host,
+                    port: pgdb::connection_port(&self.superuser_url).unwrap_or(5432),
+                    socket_dir: Some(host.as_ref()).filter(|host| host.starts_with('/')),
+                    pid: self.pid,
+                    databases: self
+                        .database_urls
+                        .iter()
+                        .map(|(spec, url)| (spec.name.as_str(), url.as_str()))
+                        .collect(),
+                };
+                let mut json =
+                    serde_json::to_string_pretty(&output).expect("output must be serializable");
+                json.push('\n');
+                json
+            }
+        }
+    }
+}
+
+/// Writes `contents` to `path` by renaming a temporary file, so readers never see partial output.
+fn write_atomically(path: &Path, contents: &str) -> anyhow::Result<()> {
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let mut file = tempfile::NamedTempFile::new_in(dir)?;
+    file.write_all(contents.as_bytes())?;
+    file.persist(path)?;
+    Ok(())
 }
 
 /// Runs an action while the configured database is available.
 fn with_database<T>(
     opts: &Opts,
-    action: impl FnOnce(&Url, &Url, bool) -> anyhow::Result<T>,
+    action: impl FnOnce(&Setup) -> anyhow::Result<T>,
 ) -> anyhow::Result<T> {
+    opts.check_databases()?;
+    let sql_files = opts.sql_files()?;
+    let environment = pgdb::config::PostgresEnvironment::read()?;
+
     if let Ok(external_url_str) = env::var("PGDB_TESTS_URL") {
         let external_url = Url::parse(&external_url_str)?;
         if external_url.scheme() != "postgres" {
@@ -108,26 +403,53 @@ fn with_database<T>(
         }
 
         let _tmp_dir = tempfile::TempDir::new()?;
-        pgdb::create_user_and_database(&external_url, &opts.db, &opts.user, &opts.password)?;
-
-        let mut user_url = external_url.clone();
-        user_url
-            .set_username(&opts.user)
-            .expect("Failed to set username");
-        user_url
-            .set_password(Some(&opts.password))
-            .expect("Failed to set password");
-        user_url.set_path(&opts.db);
-
-        action(&external_url, &user_url, true)
+        let binaries = environment.binaries();
+        let mut extensions = environment.extensions();
+        extensions.extend(opts.extensions.iter().cloned());
+        pgdb::create_user_and_database(
+            &binaries,
+            &external_url,
+            &opts.db,
+            &opts.user,
+            &opts.password,
+        )?;
+        pgdb::create_extensions(&binaries, &external_url, &opts.db, &extensions)?;
+        let user_url = external_user_url(&external_url, &opts.user, &opts.password, &opts.db);
+        load_sql_files(&sql_files, |file| {
+            pgdb::load_psql_file(&binaries, &user_url, &opts.db, file)
+        })?;
+
+        let mut database_urls = Vec::new();
+        for spec in &opts.databases {
+            pgdb::create_user_and_database(
+                &binaries,
+                &external_url,
+                &spec.name,
+                &spec.user,
+                &spec.password,
+            )?;
+            pgdb::create_extensions(&binaries, &external_url, &spec.name, &extensions)?;
+            let url = external_user_url(&external_url, &spec.user, &spec.password, &spec.name);
+            database_urls.push((spec.clone(), url));
+        }
+
+        action(&Setup {
+            superuser_url: external_url,
+            user_url,
+            database_urls,
+            external: true,
+            pid: None,
+        })
     } else {
-        let environment = pgdb::config::PostgresEnvironment::read()?;
         let mut builder = pgdb::Postgres::build();
         environment.apply(&mut builder);
 
         if let Some(superuser_pw) = &opts.superuser_pw {
             builder.superuser_pw(superuser_pw);
         }
+        if let Some(data_dir) = &opts.data_dir {
+            builder.data_dir(data_dir).persistent();
+        }
         if opts.fast() {
             builder.fast();
         }
@@ -140,10 +462,48 @@ fn with_database<T>(
         if let Some(force_shutdown_timeout) = opts.force_shutdown_timeout {
             builder.force_shutdown_timeout(Duration::from_secs(force_shutdown_timeout));
         }
+        if let Some(pg_version) = &opts.pg_version {
+            builder.version(pg_version);
+        }
+        for extension in &opts.extensions {
+            builder.extension(extension);
+        }
+        if let Some(locale) = &opts.locale {
+            builder.locale(locale);
+        }
+        if let Some(lc_collate) = &opts.lc_collate {
+            builder.lc_collate(lc_collate);
+        }
+        if let Some(lc_ctype) = &opts.lc_ctype {
+            builder.lc_ctype(lc_ctype);
+        }
+        if let Some(encoding) = &opts.encoding {
+            builder.encoding(encoding);
+        }
+        if let Some(icu_locale) = &opts.icu_locale {
+            builder.icu_locale(icu_locale);
+        }
+        if opts.data_checksums {
+            builder.data_checksums();
+        }
+        for arg in &opts.initdb_arg {
+            builder.initdb_arg(arg);
+        }
+        for config_file in &opts.config_file {
+            builder.config_file(config_file);
+        }
         for (name, value) in &opts.postgres_option {
             builder.postgres_option(name, value);
         }
-        if opts.tcp || opts.port.is_some() || environment.tcp() || environment.port().is_some() {
+        if opts.tls {
+            builder.tls();
+        }
+        if opts.tcp
+            || opts.tls
+            || opts.port.is_some()
+            || environment.tcp()
+            || environment.port().is_some()
+        {
             builder.tcp();
             let port = opts.port.or(environment.port());
             if opts.command.is_empty() {
@@ -154,43 +514,78 @@ fn with_database<T>(
         }
 
         let pg = builder.start()?;
-        pg.as_superuser().create_user(&opts.user, &opts.password)?;
-        pg.as_superuser().create_database(&opts.db, &opts.user)?;
+        let superuser = pg.as_superuser();
+        // Persistent clusters keep the users and databases from earlier runs.
+        let persistent = opts.data_dir.is_some();
+        let owners = std::iter::once((&opts.db, &opts.user, &opts.password)).chain(
+            opts.databases
+                .iter()
+                .map(|spec| (&spec.name, &spec.user, &spec.password)),
+        );
+        let mut db_created = false;
+        for (db, user, password) in owners {
+            if !(persistent && superuser.user_exists(user)?) {
+                superuser.create_user(user, password)?;
+            }
+            if !(persistent && superuser.database_exists(db)?) {
+                superuser.create_database(db, user)?;
+                db_created |= db == &opts.db;
+            }
+        }
 
-        let superuser_url = pg.as_superuser().url("postgres");
-        let user_url = pg.as_user(&opts.user, &opts.password).url(&opts.db);
-        action(&superuser_url, &user_url, false)
+        // Databases kept from earlier runs already contain the loaded SQL.
+        if db_created {
+            let client = pg.as_user(&opts.user, &opts.password);
+            load_sql_files(&sql_files, |file| client.load_sql(&opts.db, file))?;
+        }
+
+        let database_urls = opts
+            .databases
+            .iter()
+            .map(|spec| {
+                let url = pg.as_user(&spec.user, &spec.password).url(&spec.name);
+                (spec.clone(), url)
+            })
+            .collect();
+        action(&Setup {
+            superuser_url: pg.as_superuser().url("postgres"),
+            user_url: pg.as_user(&opts.user, &opts.password).url(&opts.db),
+            database_urls,
+            external: false,
+            pid: pg.pid(),
+        })
     }
 }
 
+/// Returns a URL for `db` on an external server, using the given user's credentials.
+fn external_user_url(external_url: &Url, user: &str, password: &str, db: &str) -> Url {
+    let mut user_url = external_url.clone();
+    user_url.set_username(user).expect("Failed to set username");
+    user_url
+        .set_password(Some(password))
+        .expect("Failed to set password");
+    user_url.set_path(db);
+    user_url
+}
+
 /// Runs a command with connection details for the configured database.
-fn run_command(
-    opts: &Opts,
-    superuser_url: &Url,
-    user_url: &Url,
-    mut signals: Signals,
-) -> anyhow::Result<ExitStatus> {
+fn run_command(opts: &Opts, setup: &Setup, mut signals: Signals) -> anyhow::Result<ExitStatus> {
     let (program, arguments) = opts
         .command
         .split_first()
         .expect("command must contain a program");
 
-    let host = pgdb::connection_host(user_url).expect("URL must have a host");
-    let port = pgdb::connection_port(user_url).unwrap_or(5432);
     let mut command = process::Command::new(program);
     command
         .args(arguments)
-        .env("DATABASE_URL", user_url.as_str())
-        .env("PGHOST", host.as_ref())
-        .env("PGPORT", port.to_string())
-        .env("PGUSER", &opts.user)
-        .env("PGPASSWORD", &opts.password)
-        .env("PGDATABASE", &opts.db);
+        .envs(setup.variables(opts))
+        // Readiness was already reported on behalf of the command.
+        .env_remove("NOTIFY_SOCKET");
     if opts.no_tests_cleanup {
         command.env("PGDB_TESTS_CLEANUP", "false");
     }
     if opts.export_tests_url() {
-        command.env("PGDB_TESTS_URL", superuser_url.as_str());
+        command.env("PGDB_TESTS_URL", setup.superuser_url.as_str());
     }
     let mut child = command.spawn()?;
 
@@ -221,45 +616,189 @@ fn exit_with_status(status: ExitStatus) -> ! {
     process::exit(code)
 }
 
+/// Renders the human-readable description of a setup.
+fn render_text(opts: &Opts, setup: &Setup) -> String {
+    use std::fmt::Write as _;
+
+    let Setup {
+        superuser_url,
+        user_url,
+        database_urls,
+        external,
+        ..
+    } = setup;
+    let mut text = String::new();
+    // Writing to a `String` cannot fail.
+    let _ = writeln!(text);
+    if *external {
+        let _ = writeln!(text, "Connected to external PostgreSQL instance.");
+    } else {
+        let _ = writeln!(
+            text,
+            "Postgres is now running and ready to accept connections."
+        );
+    }
+    let _ = writeln!(text);
+    let _ = writeln!(
+        text,
+        "PGHOST={}",
+        pgdb::connection_host(superuser_url).expect("URL must have a host")
+    );
+    let _ = writeln!(
+        text,
+        "PGPORT={}",
+        pgdb::connection_port(superuser_url).unwrap_or(5432)
+    );
+    let _ = writeln!(text, "Superuser access:\n\n    {superuser_url}");
+    let _ = writeln!(
+        text,
+        "\nA database named `{}`, owned by a user `{}` has been created.\n",
+        opts.db, opts.user
+    );
+    let _ = writeln!(text, "Regular user access:\n\n    {user_url}");
+    for (spec, url) in database_urls {
+        let _ = writeln!(
+            text,
+            "\nA database named `{}`, owned by a user `{}` has been created:\n\n    {url}",
+            spec.name, spec.user
+        );
+    }
+    let _ = writeln!(text, "\nYou can run `psql` with either URL to connect.");
+    if *external {
+        let _ = writeln!(
+            text,
+            "\n(Using external PostgreSQL instance from PGDB_TESTS_URL)"
+        );
+    }
+    text
+}
+
+/// Files announcing a ready server, removed when dropped.
+struct Announcement {
+    /// Locations of the files written.
+    files: Vec<PathBuf>,
+}
+
+impl Announcement {
+    /// Removes the files announcing a server from an earlier run that did not exit cleanly.
+    ///
+    /// Anyone waiting for them would otherwise consider the new server ready before it is.
+    fn remove_stale(opts: &Opts) -> anyhow::Result<()> {
+        for path in opts.output_file.iter().chain(&opts.ready_file) {
+            match fs::remove_file(path) {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => {
+                    return Err(err)
+                        .with_context(|| format!("could not remove stale {}", path.display()))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Tells everyone waiting for the server that it is ready.
+    ///
+    /// Writes `--output-file` and `--ready-file`, then notifies `--ready-fd` and the service
+    /// manager through `NOTIFY_SOCKET`.
+    fn announce(opts: &Opts, setup: &Setup) -> anyhow::Result<Self> {
+        let mut announcement = Announcement { files: Vec::new() };
+
+        if let Some(path) = &opts.output_file {
+            write_atomically(path, &setup.render(opts, opts.output))
+                .with_context(|| format!("could not write {}", path.display()))?;
+            announcement.files.push(path.clone());
+        }
+        if let Some(path) = &opts.ready_file {
+            write_atomically(path, "")
+                .with_context(|| format!("could not write {}", path.display()))?;
+            announcement.files.push(path.clone());
+        }
+        if let Some(fd) = opts.ready_fd {
+            notify_ready_fd(fd)
+                .with_context(|| format!("could not notify file descriptor {fd}"))?;
+        }
+        if let Some(notify_socket) = env::var_os("NOTIFY_SOCKET") {
+            sd_notify(
+                &notify_socket,
+                &format!("READY=1\nMAINPID={}\n", process::id()),
+            )
+            .context("could not notify service manager")?;
+        }
+
+        Ok(announcement)
+    }
+}
+
+impl Drop for Announcement {
+    fn drop(&mut self) {
+        // Stale files must not outlive the server.
+        for file in &self.files {
+            let _ = fs::remove_file(file);
+        }
+    }
+}
+
+/// Writes a newline to the inherited file descriptor `fd` and closes it.
+fn notify_ready_fd(fd: RawFd) -> io::Result<()> {
+    // SAFETY: Only checks whether `fd` is open, without taking ownership.
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: The descriptor is open and was handed to us for exclusive use by our parent.
+    let mut file = unsafe { fs::File::from_raw_fd(fd) };
+    file.write_all(b"\n")
+}
+
+/// Sends `state` to the service manager listening on `notify_socket`, see `sd_notify(3)`.
+fn sd_notify(notify_socket: &OsStr, state: &str) -> io::Result<()> {
+    let socket = UnixDatagram::unbound()?;
+    let bytes = notify_socket.as_bytes();
+    if let Some(name) = bytes.strip_prefix(b"@") {
+        // Abstract socket namespace.
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+
+            let addr = SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+            return Ok(());
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = name;
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "abstract sockets are only supported on Linux",
+            ));
+        }
+    }
+    socket.send_to(state.as_bytes(), Path::new(notify_socket))?;
+    Ok(())
+}
+
 /// Main entry point, read the `README.md` instead.
 fn main() -> anyhow::Result<()> {
     let opts = Opts::parse();
+    if opts.output_file.is_some() && opts.output == OutputFormat::Text {
+        anyhow::bail!("--output-file requires --output env, json or shell");
+    }
+    Announcement::remove_stale(&opts)?;
     let signals = Signals::new([SIGHUP, SIGINT, SIGTERM])?;
 
     if !opts.command.is_empty() {
-        let status = with_database(&opts, |superuser_url, user_url, _| {
-            run_command(&opts, superuser_url, user_url, signals)
+        let status = with_database(&opts, |setup| {
+            let _announcement = Announcement::announce(&opts, setup)?;
+            run_command(&opts, setup, signals)
         })?;
         exit_with_status(status);
     }
 
     let mut signals = signals;
-    with_database(&opts, |superuser_url, user_url, external| {
-        println!();
-        if external {
-            println!("Connected to external PostgreSQL instance.");
-        } else {
-            println!("Postgres is now running and ready to accept connections.");
-        }
-        println!();
-        println!(
-            "PGHOST={}",
-            pgdb::connection_host(superuser_url).expect("URL must have a host")
-        );
-        println!(
-            "PGPORT={}",
-            pgdb::connection_port(superuser_url).unwrap_or(5432)
-        );
-        println!("Superuser access:\n\n    {superuser_url}");
-        println!(
-            "\nA database named `{}`, owned by a user `{}` has been created.\n",
-            opts.db, opts.user
-        );
-        println!("Regular user access:\n\n    {user_url}");
-        println!("\nYou can run `psql` with either URL to connect.");
-        if external {
-            println!("\n(Using external PostgreSQL instance from PGDB_TESTS_URL)");
-        }
+    with_database(&opts, |setup| {
+        print!("{}", setup.render(&opts, opts.output));
+        io::stdout().flush()?;
+        let _announcement = Announcement::announce(&opts, setup)?;
 
         let _ = signals.forever().next();
         Ok(())
@@ -268,9 +807,12 @@ fn main() -> anyhow::Result<()> {
 
 #[cfg(test)]
 mod tests {
+    use std::os::unix::net::UnixDatagram;
+
     use clap::Parser;
+    use url::Url;
 
-    use super::Opts;
+    use super::{Announcement, Opts, OutputFormat, Setup};
 
     #[test]
     fn test_mode_enables_fast_mode_and_url_export() {
@@ -280,4 +822,139 @@ mod tests {
         assert!(opts.export_tests_url());
         assert_eq!(opts.command, ["cargo", "test"]);
     }
+
+    #[test]
+    fn stale_announcements_are_removed() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let ready_file = dir.path().join("ready");
+        std::fs::write(&ready_file, "").expect("could not write ready file");
+
+        let opts = Opts::parse_from([
+            "pgdb".as_ref(),
+            "--ready-file".as_ref(),
+            ready_file.as_os_str(),
+            "--output-file".as_ref(),
+            dir.path().join("missing.env").as_os_str(),
+        ]);
+        Announcement::remove_stale(&opts).expect("could not remove stale files");
+        assert!(!ready_file.exists());
+    }
+
+    #[test]
+    fn migrations_are_loaded_in_order_before_init_sql() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        for name in ["002_second.sql", "001_first.sql", "notes.txt"] {
+            std::fs::write(dir.path().join(name), "").expect("could not write file");
+        }
+        std::fs::create_dir(dir.path().join("003_dir.sql")).expect("could not create directory");
+
+        let opts = Opts::parse_from([
+            "pgdb".as_ref(),
+            "--init-sql".as_ref(),
+            "seed.sql".as_ref(),
+            "--migrations".as_ref(),
+            dir.path().as_os_str(),
+        ]);
+
+        assert_eq!(
+            opts.sql_files().expect("could not list SQL files"),
+            [
+                dir.path().join("001_first.sql"),
+                dir.path().join("002_second.sql"),
+                "seed.sql".into(),
+            ]
+        );
+    }
+
+    #[test]
+    fn renders_machine_readable_output() {
+        let opts = Opts::parse_from(["pgdb", "--password", "it's", "--database", "billing"]);
+        let setup = Setup {
+            superuser_url: Url::parse("postgres://postgres:su@%2Ftmp%2Fpg:5432/postgres")
+                .expect("URL must be valid"),
+            user_url: Url::parse("postgres://dev:it%27s@%2Ftmp%2Fpg:5432/dev")
+                .expect("URL must be valid"),
+            database_urls: vec![(
+                opts.databases[0].clone(),
+                Url::parse("postgres://billing:billing@%2Ftmp%2Fpg:5432/billing")
+                    .expect("URL must be valid"),
+            )],
+            external: false,
+            pid: Some(42),
+        };
+
+        let env = setup.render(&opts, OutputFormat::Env);
+        assert!(env.contains("PGHOST=/tmp/pg\n"));
+        assert!(env.contains("DATABASE_URL_BILLING=postgres://billing:billing@"));
+
+        let shell = setup.render(&opts, OutputFormat::Shell);
+        assert!(shell.contains("export PGPASSWORD='it'\\''s'\n"));
+
+        let text = setup.render(&opts, OutputFormat::Text);
+        assert!(text.contains("PGHOST=/tmp/pg\n"));
+        assert!(text.contains("Regular user access:\n\n    postgres://dev:"));
+
+        let json: serde_json::Value =
+            serde_json::from_str(&setup.render(&opts, OutputFormat::Json))
+                .expect("output must be valid JSON");
+        assert_eq!(json["socket_dir"], "/tmp/pg");
+        assert_eq!(json["port"], 5432);
+        assert_eq!(json["pid"], 42);
+        assert_eq!(
+            json["databases"]["billing"],
+            "postgres://billing:billing@%2Ftmp%2Fpg:5432/billing"
+        );
+    }
+
+    #[test]
+    fn sd_notify_sends_state_to_socket() {
+        let dir = tempfile::tempdir().expect("could not create temporary directory");
+        let path = dir.path().join("notify");
+        let receiver = UnixDatagram::bind(&path).expect("could not bind socket");
+
+        super::sd_notify(path.as_os_str(), "READY=1\n").expect("could not notify");
+
+        let mut buf = [0; 64];
+        let len = receiver.recv(&mut buf).expect("could not receive");
+        assert_eq!(&buf[..len], b"READY=1\n");
+    }
+
+    #[test]
+    fn database_specs_default_user_and_password() {
+        let opts = Opts::parse_from([
+            "pgdb",
+            "--database",
+            "billing",
+            "--database",
+            "audit-log:auditor",
+            "--database",
+            "search:indexer:secret:with:colons",
+        ]);
+
+        let specs: Vec<_> = opts
+            .databases
+            .iter()
+            .map(|spec| {
+                (
+                    spec.name.as_str(),
+                    spec.user.as_str(),
+                    spec.password.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            specs,
+            [
+                ("billing", "billing", "billing"),
+                ("audit-log", "auditor", "auditor"),
+                ("search", "indexer", "secret:with:colons"),
+            ]
+        );
+        assert_eq!(opts.databases[1].url_var(), "DATABASE_URL_AUDIT_LOG");
+        assert!(opts.check_databases().is_ok());
+
+        let opts = Opts::parse_from(["pgdb", "--database", "other:dev"]);
+        assert!(opts.check_databases().is_err());
+        assert!(Opts::try_parse_from(["pgdb", "--database", ":user"]).is_err());
+    }
 }